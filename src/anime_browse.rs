@@ -0,0 +1,765 @@
+extern crate crossterm;
+
+use std::path::{Component, PathBuf};
+use std::{thread, env};
+use std::process::exit;
+use std::ffi::OsStr;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use crossterm::{execute, Result, terminal};
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::style::{Attribute, Print, SetAttribute};
+
+use anime_cli::{anime_config, anime_disk, anime_format, anime_history, anime_part, anime_playlist, anime_probe, anime_release, anime_trash, is_valid_media_file};
+use anime_cli::anime_probe::MediaInfo;
+use anime_cli::anime_sort::{SortOrder, sort_listings};
+use anime_cli::anime_watch::{AnimeListing, NextEpisode, episode_paths, get_anime_listings, next_episode, play_video};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+const HEADER_LINES: u16 = 2; // folder and page
+const FOOTER_LINES: u16 = 3; // status and two lines of keys
+const DETAIL_LINES: u16 = 5; // about the selected listing, under a rule
+const MIN_ROWS: u16 = 3; // listing rows the details make way for on short terminals
+const COMMAND_LETTERS: &str = "QNS"; // Shift and these is the command, not a jump
+const HELP: [&str; 2] = [
+    "Arrows/PgUp/PgDn/Home/End move | Enter open or play | Esc back | / filter | Shift+letter jump (not Q/N/S) | o sort | s hidden | n next",
+    "Space select | a select all | q play | w watched | c check CRC | m move | e export playlist | Del trash | u undo",
+];
+
+static IN_VIRTUAL_SCREEN: AtomicBool = AtomicBool::new(false);
+
+pub fn browse_anime_listings() -> Result<()> {
+    execute!(stdout(), EnterAlternateScreen, Hide)?;
+    IN_VIRTUAL_SCREEN.store(true, Ordering::SeqCst);
+    terminal::enable_raw_mode()?; // keys come in one at a time, Ctrl+C included
+    let anime_dir = match env::current_dir() {
+        Ok(path) => path,
+        Err(_) => { restore_terminal(); eprintln!("Do you have permission to modify this folder?"); exit(1) }
+    };
+
+    let mut sub_dir = anime_dir.clone();
+    let mut prefix = anime_dir.clone();
+    prefix.pop();
+
+    let mut selected = 0; // index into the listings that pass the filter
+    let mut reselect: Option<String> = None; // name to put the cursor back on once the listings change
+    let mut filter = String::new();
+    let mut filtering = false; // keys go into the filter while this is on
+    let mut show_empty_folders = false; // allow users to see and delete empty folders and non media items
+    let mut sort = anime_config::Config::load().get("sort").and_then(|order| order.parse().ok()).unwrap_or(SortOrder::Natural);
+    let mut status: Option<String> = None; // shown above the keys until the next key press
+    let mut crc_checks: HashMap<PathBuf, String> = HashMap::new(); // checking reads the whole file, so once is enough
+    let mut probes: HashMap<PathBuf, Option<MediaInfo>> = HashMap::new();
+    let mut trashed: Vec<Vec<anime_trash::TrashEntry>> = vec![]; // this session's deletions, u puts back the last one
+    let mut marked: HashSet<PathBuf> = HashSet::new(); // what bulk actions work on, the highlighted listing when it's empty
+
+    'main: loop {
+        let mut all_listings = get_anime_listings(sub_dir.clone(), show_empty_folders);
+        sort_listings(&mut all_listings, sort);
+
+        'pages: loop {
+            let listings: Vec<AnimeListing> = all_listings.iter()
+                .filter(|listing| fuzzy_match(&filter, &listing.name))
+                .cloned()
+                .collect();
+            // Bulk actions only take what the filter shows, the rest stays selected
+            let shown_marks: Vec<AnimeListing> = listings.iter().filter(|listing| marked.contains(&listing.path)).cloned().collect();
+            let hidden_marks = all_listings.iter().filter(|listing| marked.contains(&listing.path)).count() - shown_marks.len();
+            if let Some(name) = reselect.take() {
+                selected = listings.iter().position(|listing| listing.name == name).unwrap_or(0);
+            }
+            if selected >= listings.len() {
+                selected = listings.len().saturating_sub(1);
+            }
+            let (width, height) = terminal::size()?;
+            let show_details = height >= HEADER_LINES + FOOTER_LINES + DETAIL_LINES + 1 + MIN_ROWS;
+            let detail_height = if show_details { DETAIL_LINES + 1 } else { 0 };
+            let page_size = std::cmp::max(1, height.saturating_sub(HEADER_LINES + FOOTER_LINES + detail_height)) as usize;
+            let max_pages = std::cmp::max(1, (listings.len() + page_size - 1) / page_size);
+            let current_page = selected / page_size + 1;
+            let current_position = (current_page - 1) * page_size;
+
+            execute!(stdout(), terminal::Clear(terminal::ClearType::All))?;
+            draw_line(0, &format!("\\{}", sub_dir.strip_prefix(&prefix).unwrap().to_str().unwrap()), width)?;
+            let selection = match (shown_marks.len(), hidden_marks) {
+                (0, 0) => String::new(),
+                (shown, 0) => format!(" | {} selected", shown),
+                (shown, hidden) => format!(" | {} selected, {} more hidden by the filter", shown, hidden),
+            };
+            draw_line(1, &format!("List of animes | Page {} of {} | Sorted by {}{}{}", current_page, max_pages, sort,
+                                  if show_empty_folders { " | Showing hidden items" } else { "" }, selection), width)?;
+
+            if listings.len() > 0 {
+                let page = &listings[current_position..std::cmp::min(listings.len(), current_position + page_size)];
+                for (i, listing) in page.iter().enumerate() {
+                    let line = HEADER_LINES + i as u16;
+                    let row = format!("{}{}", if marked.contains(&listing.path) { "*" } else { " " }, listing_row(listing));
+                    if current_position + i == selected {
+                        execute!(stdout(), MoveTo(0, line), SetAttribute(Attribute::Reverse),
+                                 Print(fit(&row, width, true)), SetAttribute(Attribute::Reset))?;
+                    } else {
+                        draw_line(line, &row, width)?;
+                    }
+                }
+            } else if !filter.is_empty() {
+                draw_line(HEADER_LINES, &format!(" Nothing matches {}", filter), width)?;
+            } else {
+                draw_line(HEADER_LINES, " Nothing here", width)?;
+            }
+
+            let status_line = height.saturating_sub(FOOTER_LINES);
+            if show_details {
+                let rule = status_line - detail_height;
+                draw_line(rule, &"-".repeat(width as usize), width)?;
+                if let Some(listing) = listings.get(selected) {
+                    let media = match listing.is_media {
+                        true => probes.entry(listing.path.clone()).or_insert_with(|| anime_probe::probe(&listing.path).ok()).as_ref(),
+                        false => None,
+                    };
+                    for (i, line) in listing_details(listing, &crc_checks, media).iter().take(DETAIL_LINES as usize).enumerate() {
+                        draw_line(rule + 1 + i as u16, line, width)?;
+                    }
+                }
+            }
+            if filtering {
+                draw_line(status_line, &format!("Filter: {}", filter), width)?;
+            } else if let Some(message) = status.take() {
+                draw_line(status_line, &message, width)?;
+            } else if !filter.is_empty() {
+                draw_line(status_line, &format!("Filter: {} (Esc to clear)", filter), width)?;
+            }
+            for (i, help) in HELP.iter().enumerate() {
+                draw_line(status_line + 1 + i as u16, help, width)?;
+            }
+            if filtering {
+                execute!(stdout(), MoveTo(("Filter: ".len() + filter.chars().count()) as u16, status_line), Show)?;
+            } else {
+                execute!(stdout(), Hide)?;
+            }
+
+            let event = match read()? {
+                Event::Key(event) => event,
+                _ => continue 'pages, // resizes too, the next round lays the page out again
+            };
+            if event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL) {
+                break 'main;
+            }
+            let current = listings.get(selected).cloned();
+            let targets: Vec<AnimeListing> = match shown_marks.is_empty() {
+                true => current.iter().cloned().collect(),
+                false => shown_marks.clone(),
+            };
+
+            if filtering {
+                match event.code {
+                    KeyCode::Char(c) => {
+                        filter.push(c);
+                        selected = 0;
+                        continue 'pages;
+                    },
+                    KeyCode::Backspace => {
+                        filter.pop();
+                        selected = 0;
+                        continue 'pages;
+                    },
+                    KeyCode::Enter => { // keep the filter, the keys work as usual again
+                        filtering = false;
+                        continue 'pages;
+                    },
+                    KeyCode::Esc => {
+                        filtering = false;
+                        filter.clear();
+                        reselect = current.map(|listing| listing.name);
+                        continue 'pages;
+                    },
+                    _ => {}, // arrows and the like still move around
+                }
+            }
+
+            // Q, N and S were commands before Shift jumped to a letter, so they still are
+            let code = match event.code {
+                KeyCode::Char(c) if COMMAND_LETTERS.contains(c) => KeyCode::Char(c.to_ascii_lowercase()),
+                code => code,
+            };
+            match code {
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down => {
+                    if selected + 1 < listings.len() { selected += 1; }
+                },
+                KeyCode::Left | KeyCode::PageUp => selected = selected.saturating_sub(page_size),
+                KeyCode::Right | KeyCode::PageDown => {
+                    if current_page < max_pages { selected = std::cmp::min(selected + page_size, listings.len() - 1); }
+                },
+                KeyCode::Home => selected = 0,
+                KeyCode::End => selected = listings.len().saturating_sub(1),
+                KeyCode::Char('/') => filtering = true,
+                KeyCode::Char(c) if c.is_uppercase() => { // next name starting with the letter, wrapping around
+                    let letter = c.to_lowercase().to_string();
+                    if let Some(found) = (1..=listings.len())
+                        .map(|step| (selected + step) % listings.len())
+                        .find(|&i| listings[i].name.to_lowercase().starts_with(&letter)) {
+                        selected = found;
+                    }
+                },
+                KeyCode::Char('q') if !shown_marks.is_empty() => {
+                    let files = media_files(&targets);
+                    if !files.is_empty() {
+                        let handle = play_video(files.iter().map(|file| file.to_string_lossy().to_string()).collect(), sub_dir.clone());
+                        show_message(&[format!("Now playing {} selected episode(s)", files.len())])?;
+                        handle.join().unwrap();
+                        while poll(Duration::from_millis(50))? { read()?; }
+                        reselect = current.map(|listing| listing.name);
+                        break 'pages;
+                    }
+                },
+                KeyCode::Char('q') => {
+                    if let Some(_) = listings.iter().find(|x| x.is_media) {
+                        let handle = call_play_videos(listings.clone());
+                        show_message(&[format!("Now playing all episodes in {}", sub_dir.file_name().unwrap_or_default().to_string_lossy())])?;
+                        handle.join().unwrap();
+                        while poll(Duration::from_millis(50))? { read()?; } // drop keys pressed while the player was up
+                        reselect = current.map(|listing| listing.name); // refresh what's watched
+                        break 'pages;
+                    }
+                },
+                KeyCode::Char('n') => {
+                    let series_path = match &current {
+                        Some(listing) if listing.is_dir => listing.path.clone(),
+                        _ => sub_dir.clone(),
+                    };
+                    let series_name = series_path.file_name().and_then(OsStr::to_str).unwrap_or("").to_owned();
+                    match next_episode(&series_path) {
+                        NextEpisode::Play(path) => {
+                            let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                            let handle = play_video(vec![filename.clone()], path.parent().unwrap().to_path_buf());
+                            show_message(&[format!("Now playing {}", filename)])?;
+                            handle.join().unwrap();
+                            while poll(Duration::from_millis(50))? { read()?; }
+                        },
+                        NextEpisode::Download { title, season, episode, .. } => {
+                            status = Some(format!("Season {} episode {} of {} isn't downloaded yet, run anime-cli next {} to get it", season, episode, title, title));
+                        },
+                        NextEpisode::Nothing => {
+                            status = Some(format!("Everything in {} is watched", series_name));
+                        },
+                    }
+                    reselect = current.map(|listing| listing.name);
+                    break 'pages;
+                },
+                KeyCode::Char(' ') => {
+                    if let Some(listing) = current {
+                        if !marked.remove(&listing.path) {
+                            marked.insert(listing.path);
+                        }
+                        if selected + 1 < listings.len() { selected += 1; }
+                    }
+                },
+                KeyCode::Char('a') => { // everything shown, or nothing when that's all selected already
+                    if listings.iter().all(|listing| marked.contains(&listing.path)) {
+                        for listing in &listings {
+                            marked.remove(&listing.path);
+                        }
+                    } else {
+                        marked.extend(listings.iter().map(|listing| listing.path.clone()));
+                    }
+                },
+                KeyCode::Enter => {
+                    if let Some(listing) = current {
+                        if listing.is_dir {
+                            sub_dir = sub_dir.join(&listing.name);
+                            selected = 0;
+                            filter.clear();
+                            marked.clear();
+                            break 'pages;
+                        } else if listing.is_media {
+                            let handle = call_play_video(listing.clone());
+                            show_message(&[format!("Now playing {}", listing.name)])?;
+
+                            handle.join().unwrap();
+                            while poll(Duration::from_millis(50))? { read()?; }
+                            reselect = Some(listing.name);
+                            break 'pages;
+                        } else if anime_playlist::is_playlist(&listing.path) {
+                            let files: Vec<PathBuf> = match anime_playlist::read(&listing.path) {
+                                Ok(files) => files.into_iter().filter(|file| file.is_file()).collect(),
+                                Err(e) => {
+                                    status = Some(format!("Could not read {}: {}", listing.name, e));
+                                    continue 'pages;
+                                },
+                            };
+                            if files.is_empty() {
+                                status = Some(format!("None of the files in {} are here", listing.name));
+                                continue 'pages;
+                            }
+                            let handle = play_video(files.iter().map(|file| file.to_string_lossy().to_string()).collect(), PathBuf::new());
+                            show_message(&[format!("Now playing {} ({} episode(s))", listing.name, files.len())])?;
+                            handle.join().unwrap();
+                            while poll(Duration::from_millis(50))? { read()?; }
+                            reselect = Some(listing.name);
+                            break 'pages;
+                        }
+                    }
+                },
+                KeyCode::Esc => {
+                    if !filter.is_empty() {
+                        filter.clear();
+                        reselect = current.map(|listing| listing.name);
+                    } else if sub_dir == anime_dir {
+                        break 'main; // Bye
+                    } else {
+                        reselect = sub_dir.file_name().and_then(OsStr::to_str).map(str::to_string);
+                        sub_dir.pop();
+                        marked.clear();
+                        break 'pages;
+                    }
+                },
+                KeyCode::Delete => {
+                    if !targets.is_empty() {
+                        let what = match &targets[..] {
+                            [listing] => listing.name.clone(),
+                            _ => format!("the {} selected items", targets.len()),
+                        };
+                        let mut message = vec![format!("Move {} to the trash?", what),
+                                               "Press delete to confirm, press any key to cancel".to_string()];
+                        if hidden_marks > 0 && !shown_marks.is_empty() {
+                            message.insert(1, format!("The {} selected item(s) hidden by the filter stay", hidden_marks));
+                        }
+                        show_message(&message)?;
+                        if let Event::Key(KeyEvent { code: KeyCode::Delete, .. }) = read()? {
+                            let mut batch = vec![];
+                            let mut errors = vec![];
+                            let mut untrashable = vec![];
+                            for listing in &targets {
+                                match anime_trash::trash(&listing.path) {
+                                    Ok(entry) => batch.push(entry),
+                                    Err(e) if e.kind() == std::io::ErrorKind::Unsupported => untrashable.push(listing),
+                                    Err(e) => errors.push(e.to_string()),
+                                }
+                            }
+                            let mut deleted = 0;
+                            if !untrashable.is_empty() {
+                                let what = match &untrashable[..] {
+                                    [listing] => listing.name.clone(),
+                                    _ => format!("{} of them", untrashable.len()),
+                                };
+                                show_message(&[format!("No trash on its drive can take {}, delete for good?", what),
+                                               "Press delete to confirm, press any key to keep it".to_string()])?;
+                                if let Event::Key(KeyEvent { code: KeyCode::Delete, .. }) = read()? {
+                                    for listing in untrashable {
+                                        match anime_trash::delete(&listing.path) {
+                                            Ok(_) => deleted += 1,
+                                            Err(e) => errors.push(format!("could not delete {}: {}", listing.name, e)),
+                                        }
+                                    }
+                                } else {
+                                    errors.push(format!("kept {}", what));
+                                }
+                            }
+                            status = Some(match (errors.first(), deleted) {
+                                (None, 0) => format!("Moved {} to the trash, u to undo", what),
+                                (None, _) if batch.is_empty() => format!("Deleted {} for good", what),
+                                (error, _) => format!("{} of {} moved to the trash, {} deleted for good{}", batch.len(), targets.len(), deleted,
+                                                      error.map(|error| format!(", {}", error)).unwrap_or_default()),
+                            });
+                            if !batch.is_empty() {
+                                trashed.push(batch);
+                            }
+                            for listing in &targets {
+                                marked.remove(&listing.path);
+                            }
+                            break 'pages;
+                        }
+                    }
+                },
+                KeyCode::Char('w') => { // watched, or unwatched when they all are already
+                    let files = media_files(&targets);
+                    let states = anime_history::watch_states();
+                    let watched = !files.iter().all(|file| states.get(&anime_history::absolute_path(file)).map_or(false, |state| state.completed));
+                    status = Some(match anime_history::set_watched(&files, watched) {
+                        Ok(_) => format!("Marked {} episode(s) as {}", files.len(), if watched { "watched" } else { "not watched" }),
+                        Err(e) => format!("Could not mark them: {}", e),
+                    });
+                    reselect = current.map(|listing| listing.name);
+                    break 'pages;
+                },
+                KeyCode::Char('m') => {
+                    if !targets.is_empty() {
+                        let folder = match read_input(status_line, width, "Move to folder (in the anime folder): ", "")? {
+                            Some(folder) if !folder.trim().is_empty() => PathBuf::from(folder.trim()),
+                            _ => continue 'pages,
+                        };
+                        if !folder.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+                            status = Some(format!("{} is not a folder in the anime folder", folder.display()));
+                            continue 'pages;
+                        }
+                        let folder = anime_dir.join(folder);
+                        let mut moved = 0;
+                        let mut errors = vec![];
+                        if let Err(e) = std::fs::create_dir_all(&folder) {
+                            errors.push(e.to_string());
+                        }
+                        for listing in &targets {
+                            if !errors.is_empty() {
+                                break;
+                            }
+                            let to = folder.join(&listing.name);
+                            let result = if to.exists() {
+                                Err(format!("{} already exists", to.display()))
+                            } else {
+                                std::fs::rename(&listing.path, &to).map_err(|e| e.to_string())
+                            };
+                            match result {
+                                Ok(_) => {
+                                    let _ = anime_history::move_watch_states(&listing.path, &to);
+                                    moved += 1;
+                                },
+                                Err(e) => errors.push(e),
+                            }
+                        }
+                        status = Some(match errors.first() {
+                            Some(error) => format!("{} of {} moved, {}", moved, targets.len(), error),
+                            None => format!("Moved {} item(s) to {}", moved, folder.display()),
+                        });
+                        for listing in &targets {
+                            marked.remove(&listing.path);
+                        }
+                        break 'pages;
+                    }
+                },
+                KeyCode::Char('e') => {
+                    let files = media_files(&targets);
+                    if !files.is_empty() {
+                        let name = format!("{}.m3u8", sub_dir.file_name().and_then(OsStr::to_str).unwrap_or("playlist"));
+                        let playlist = match read_input(status_line, width, "Save playlist as: ", &name)? {
+                            Some(playlist) if !playlist.trim().is_empty() => sub_dir.join(playlist.trim()),
+                            _ => continue 'pages,
+                        };
+                        let absolute = anime_config::Config::load().is_enabled("absolute_playlists");
+                        status = Some(match anime_playlist::write(&playlist, &files, absolute) {
+                            Ok(_) => format!("Wrote {} episode(s) to {}", files.len(), playlist.display()),
+                            Err(e) => format!("Could not write {}: {}", playlist.display(), e),
+                        });
+                        break 'pages;
+                    }
+                },
+                KeyCode::Char('o') => { // remembered for next time, a config that can't be saved just means it isn't
+                    sort = sort.next();
+                    let mut config = anime_config::Config::load();
+                    if config.set("sort", &sort.to_string()).is_ok() {
+                        let _ = config.save();
+                    }
+                    sort_listings(&mut all_listings, sort);
+                    reselect = current.map(|listing| listing.name);
+                    status = Some(format!("Sorted by {}", sort));
+                },
+                KeyCode::Char('c') => {
+                    let files = media_files(&targets);
+                    let (mut good, mut bad, mut unchecked) = (0, 0, 0);
+                    let checked = check_crcs(&files, status_line, width)?;
+                    let stopped = checked.len() < files.len();
+                    let mut last = String::new();
+                    for (file, verification) in files.iter().zip(checked) {
+                        let name = file.file_name().and_then(OsStr::to_str).unwrap_or("").to_string();
+                        let result = match verification {
+                            Ok(anime_part::Verification::Match(crc)) => { good += 1; format!("CRC {:08X} checks out", crc) },
+                            Ok(anime_part::Verification::Mismatch { expected, actual }) => { bad += 1; format!("CRC is {:08X}, the name says {:08X}", actual, expected) },
+                            Ok(anime_part::Verification::NoChecksum) => { unchecked += 1; "No CRC in the name to check".to_string() },
+                            Err(e) => { bad += 1; format!("Could not check the CRC: {}", e) },
+                        };
+                        last = format!("{}: {}", name, result);
+                        crc_checks.insert(file.clone(), result);
+                    }
+                    status = Some(match files.len() {
+                        0 => "No episodes to check".to_string(),
+                        n if stopped => format!("CRC check stopped, {} of {} checked: {} OK, {} bad, {} without a CRC", good + bad + unchecked, n, good, bad, unchecked),
+                        1 => last,
+                        n => format!("{} checked: {} OK, {} bad, {} without a CRC", n, good, bad, unchecked),
+                    });
+                },
+                KeyCode::Char('u') => {
+                    if let Some(batch) = trashed.pop() {
+                        let mut left = vec![];
+                        let mut errors = vec![];
+                        for entry in batch {
+                            let name = entry.original.file_name().and_then(OsStr::to_str).unwrap_or("").to_string();
+                            match anime_trash::restore(&entry) {
+                                Ok(_) if entry.original.parent() == Some(sub_dir.as_path()) => reselect = Some(name),
+                                Ok(_) => {},
+                                Err(e) => {
+                                    errors.push(format!("Could not restore {}: {}", name, e));
+                                    left.push(entry);
+                                },
+                            }
+                        }
+                        status = Some(errors.first().cloned().unwrap_or("Restored what was deleted last".to_string()));
+                        if !left.is_empty() {
+                            trashed.push(left);
+                        }
+                        break 'pages;
+                    }
+                    status = Some("Nothing to undo".to_string());
+                },
+                KeyCode::Char('s') => {
+                    show_empty_folders = !show_empty_folders;
+                    reselect = current.map(|listing| listing.name);
+                    break 'pages;
+                },
+                _ => {},
+            }
+        }
+    }
+    terminal::disable_raw_mode()?;
+    execute!(stdout(), LeaveAlternateScreen, Show)?;
+    IN_VIRTUAL_SCREEN.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Lets the user type a line on screen line `line` after `label`, starting from `initial`.
+/// None when Esc cancels it
+fn read_input(line: u16, width: u16, label: &str, initial: &str) -> Result<Option<String>> {
+    let mut input = initial.to_string();
+    loop {
+        execute!(stdout(), MoveTo(0, line), terminal::Clear(terminal::ClearType::CurrentLine))?;
+        draw_line(line, &format!("{}{}", label, input), width)?;
+        execute!(stdout(), Show)?;
+        if let Event::Key(event) = read()? {
+            match event.code {
+                KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => { input.pop(); },
+                KeyCode::Enter => return Ok(Some(input)),
+                KeyCode::Esc => return Ok(None),
+                _ => {},
+            }
+        }
+    }
+}
+
+/// What the CRC check worker reports back
+enum CrcProgress {
+    Reading { index: usize, read: u64 },
+    Checked(std::io::Result<anime_part::Verification>),
+}
+
+/// Checks `files` against the CRCs in their names on a worker thread, showing how far it got on `line`.
+/// Esc stops it, only the files checked to the end are returned
+fn check_crcs(files: &[PathBuf], line: u16, width: u16) -> Result<Vec<std::io::Result<anime_part::Verification>>> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    let worker = {
+        let (files, cancelled) = (files.to_vec(), cancelled.clone());
+        thread::spawn(move || {
+            for (index, file) in files.iter().enumerate() {
+                let result = anime_part::verify_with_progress(file, |read| {
+                    let _ = sender.send(CrcProgress::Reading { index, read });
+                    !cancelled.load(Ordering::Relaxed)
+                });
+                if cancelled.load(Ordering::Relaxed) || sender.send(CrcProgress::Checked(result)).is_err() {
+                    break;
+                }
+            }
+        })
+    };
+
+    let sizes: Vec<u64> = files.iter().map(|file| file.metadata().map_or(0, |metadata| metadata.len())).collect();
+    let mut checked = vec![];
+    let mut shown = None;
+    loop {
+        while poll(Duration::from_millis(0))? {
+            if let Event::Key(KeyEvent { code: KeyCode::Esc, .. }) = read()? {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+        }
+        let (index, read) = match receiver.recv_timeout(Duration::from_millis(50)) {
+            Ok(CrcProgress::Reading { index, read }) => (index, read),
+            Ok(CrcProgress::Checked(result)) => {
+                checked.push(result);
+                (checked.len(), 0)
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let percent = read * 100 / sizes.get(index).copied().unwrap_or(0).max(1);
+        if index < files.len() && shown != Some((index, percent)) {
+            shown = Some((index, percent));
+            let name = files[index].file_name().and_then(OsStr::to_str).unwrap_or("");
+            draw_line(line, &format!("Checking the CRC of {} ({} of {}), {}% | Esc stops", name, index + 1, files.len(), percent), width)?;
+        }
+    }
+    let _ = worker.join();
+    Ok(checked)
+}
+
+/// The episodes in `listings` in watching order, folders give everything under them
+fn media_files(listings: &[AnimeListing]) -> Vec<PathBuf> {
+    listings.iter()
+        .flat_map(|listing| match listing.is_dir {
+            true => episode_paths(&listing.path, false),
+            false if listing.is_media => vec![listing.path.clone()],
+            false => vec![],
+        })
+        .collect()
+}
+
+/// Writes `text` on screen line `line`, cut to the terminal `width` so it can't wrap into the next one
+fn draw_line(line: u16, text: &str, width: u16) -> Result<()> {
+    execute!(stdout(), MoveTo(0, line), Print(fit(text, width, false)))
+}
+
+/// Clears the screen for a few lines of text, e.g. while the player is up
+fn show_message(lines: &[String]) -> Result<()> {
+    let (width, _) = terminal::size()?;
+    execute!(stdout(), terminal::Clear(terminal::ClearType::All), Hide)?;
+    for (i, line) in lines.iter().enumerate() {
+        draw_line(i as u16, line, width)?;
+    }
+    Ok(())
+}
+
+/// `text` cut short of the last column, which would make some terminals wrap. With `pad` it fills the line,
+/// so a highlight covers the whole row
+fn fit(text: &str, width: u16, pad: bool) -> String {
+    let width = width.saturating_sub(1) as usize;
+    let mut fitted: String = text.chars().take(width).collect();
+    if pad {
+        let length = fitted.chars().count();
+        fitted.extend(std::iter::repeat(' ').take(width - length));
+    }
+    fitted
+}
+
+/// What the browser shows for a listing, e.g. `Show | 2 season(s), 24 episode(s), 3 watched`
+fn listing_row(listing: &AnimeListing) -> String {
+    let watched = match listing.watched_count {
+        0 => String::new(),
+        n if n == listing.episode_count => ", all watched".to_string(),
+        n => format!(", {} watched", n),
+    };
+    if listing.season_count > 1 {
+        format!("{} | {} season(s), {} episode(s){}", listing.name, listing.season_count, listing.episode_count, watched)
+    } else if listing.is_dir {
+        format!("{} | {} episode(s){}", listing.name, listing.episode_count, watched)
+    } else {
+        let state = match &listing.watch_state {
+            Some(state) if state.completed => " | watched".to_string(),
+            Some(state) => match state.resume_position() {
+                Some(position) => format!(" | stopped at {}", anime_format::format_position(position)),
+                None => String::new(),
+            },
+            None => String::new(),
+        };
+        format!("{}{}", listing.name, state)
+    }
+}
+
+/// What the detail pane shows: for a folder its size and how much was watched, for a file
+/// what its release name says, what its headers say, its size, CRC and where playback got to
+fn listing_details(listing: &AnimeListing, crc_checks: &HashMap<PathBuf, String>, media: Option<&MediaInfo>) -> Vec<String> {
+    let size = anime_disk::format_bytes(listing.size);
+    let mut lines = vec![listing.name.clone()];
+    if listing.is_dir {
+        lines.push(match listing.season_count {
+            0 | 1 => format!("{} episode(s), {}", listing.episode_count, size),
+            seasons => format!("{} episode(s) in {} seasons, {}", listing.episode_count, seasons, size),
+        });
+        let last_played = listing.last_watched
+            .map(|time| format!(", last played {}", anime_history::format_time(time)))
+            .unwrap_or_default();
+        lines.push(format!("{} of {} watched{}", listing.watched_count, listing.episode_count, last_played));
+        return lines;
+    }
+    if !listing.is_media {
+        lines.push(size);
+        return lines;
+    }
+
+    let release = anime_release::parse(&listing.name);
+    let mut parts = vec![];
+    if let Some(group) = &release.group {
+        parts.push(group.clone());
+    }
+    match (&release.episode, release.season) {
+        (Some(episode), Some(season)) => parts.push(format!("Season {} episode {}", season, episode)),
+        (Some(episode), None) => parts.push(format!("Episode {}", episode)),
+        _ => {},
+    }
+    if let Some(version) = release.version {
+        parts.push(format!("v{}", version));
+    }
+    if let Some(resolution) = release.resolution {
+        parts.push(format!("{}p", resolution));
+    }
+    if let Some(codec) = &release.codec {
+        parts.push(codec.clone());
+    }
+    lines.push(if parts.is_empty() { "Nothing to read from the name".to_string() } else { parts.join(" | ") });
+    lines.push(match media {
+        Some(info) => format!("{} | {}", info.container, info.summary()),
+        None => "No media info, only Matroska and MP4 headers can be read".to_string(),
+    });
+
+    let crc = match (crc_checks.get(&listing.path), release.crc) {
+        (Some(checked), _) => checked.clone(),
+        (None, Some(crc)) => format!("CRC {:08X}, c to check it", crc),
+        (None, None) => "No CRC in the name".to_string(),
+    };
+    lines.push(format!("{} | {}", size, crc));
+
+    lines.push(match &listing.watch_state {
+        Some(state) => {
+            let length = if state.duration > 0.0 { format!(" of {}", anime_format::format_position(state.duration)) } else { String::new() };
+            match state.resume_position() {
+                _ if state.completed => format!("Watched{}, last played {}", length, anime_history::format_time(state.time)),
+                Some(position) => format!("Stopped at {}{}", anime_format::format_position(position), length),
+                None => format!("Played{}, last on {}", length, anime_history::format_time(state.time)),
+            }
+        },
+        None => "Not played yet".to_string(),
+    });
+    lines
+}
+
+/// Whether the letters of `pattern` appear in `name` in order, ignoring case and spaces in the pattern,
+/// so `snk` finds `Shingeki no Kyojin`
+fn fuzzy_match(pattern: &str, name: &str) -> bool {
+    let mut name = name.chars().flat_map(char::to_lowercase);
+    pattern.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .all(|wanted| name.any(|c| c == wanted))
+}
+
+/// Puts the terminal back the way we found it, safe to call from a signal handler at any point
+pub fn restore_terminal() {
+    if IN_VIRTUAL_SCREEN.swap(false, Ordering::SeqCst) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(stdout(), LeaveAlternateScreen);
+    }
+    let _ = execute!(stdout(), Show);
+}
+
+fn call_play_video(episode: AnimeListing) -> thread::JoinHandle<()> {
+    let mut filename = vec![];
+    filename.push(episode.name);
+    let dir_path = episode.path.parent().unwrap().to_path_buf();
+
+    play_video(filename, dir_path)
+}
+
+fn call_play_videos(listings: Vec<AnimeListing>) -> thread::JoinHandle<()> {
+    let mut episodes = vec![];
+    for listing in listings {
+        if !listing.is_dir && is_valid_media_file(listing.path.extension().and_then(OsStr::to_str).unwrap()) {
+            episodes.push(listing);
+        }
+    }
+    let filenames: Vec<String> = episodes.clone().into_iter().map(|episode| episode.name).collect();
+    let dir_path = episodes[0].path.parent().unwrap().to_path_buf();
+
+    play_video(filenames, dir_path)
+}
@@ -0,0 +1,113 @@
+extern crate dirs;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{anime_organize, anime_player};
+use crate::anime_proxy::Proxy;
+use crate::anime_sort::SortOrder;
+
+const APP_DIR: &str = "anime-cli";
+const CONFIG_FILE: &str = "config";
+
+/// Settings `anime-cli config` knows about, with what they do
+pub const KEYS: &'static [(&'static str, &'static str)] = &[
+    ("resolution", "Resolution added to searches when -r isn't given, 0 for none (default 720)"),
+    ("proxy", "Proxy used when neither -p nor ANIME_CLI_PROXY/ALL_PROXY is set"),
+    ("noshow", "true to never open the player when a download starts"),
+    ("organize", "true to rename and move finished downloads using the templates"),
+    ("template", "Where organized episodes go, see README (default {title}/Season {season:02}/{title} - S{season:02}E{episode:02} [{resolution}][{crc}].{ext})"),
+    ("movie_template", "Where organized downloads without an episode number go (default {title}/{title} [{resolution}].{ext})"),
+    ("trash", "Folder deleted shows and episodes are moved to (default the desktop trash, ~/.local/share/Trash on Linux)"),
+    ("absolute_playlists", "true to write absolute paths in exported playlists instead of paths relative to the playlist"),
+    ("player", "Player for episodes: builtin (libmpv), mpv, vlc, mplayer, system or a command like \"celluloid {files}\" (default builtin, system without mpv)"),
+    ("sort", "Order of the browser: natural, name, modified, size, episodes or watched (default natural)"),
+];
+
+/// `key=value` settings stored in the user's config directory
+pub struct Config {
+    values: BTreeMap<String, String>,
+}
+
+/// `~/.config/anime-cli` on Linux, where the config file lives
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR))
+}
+
+/// `~/.local/share/anime-cli` on Linux, where the queue and history live
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_DIR))
+}
+
+impl Config {
+    /// Reads the config file, a missing or unreadable one gives an empty config
+    pub fn load() -> Config {
+        let mut values = BTreeMap::new();
+        let content = config_dir()
+            .and_then(|dir| fs::read_to_string(dir.join(CONFIG_FILE)).ok())
+            .unwrap_or_default();
+        for line in content.lines() {
+            let mut kv = line.splitn(2, '=');
+            if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+                values.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        Config { values }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let dir = config_dir()
+            .ok_or(std::io::Error::new(std::io::ErrorKind::NotFound, "Could not find a config directory"))?;
+        fs::create_dir_all(&dir)?;
+        let mut file = fs::File::create(dir.join(CONFIG_FILE))?;
+        for (key, value) in &self.values {
+            writeln!(file, "{}={}", key, value)?;
+        }
+        file.flush()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Sets `key` after checking it's a known setting and `value` makes sense for it
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        validate(key, value)?;
+        self.values.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Goes back to the default, returns false if `key` wasn't set
+    pub fn unset(&mut self, key: &str) -> bool {
+        self.values.remove(key).is_some()
+    }
+
+    /// Whether a boolean setting is turned on
+    pub fn is_enabled(&self, key: &str) -> bool {
+        self.get(key).map_or(false, |value| value == "true")
+    }
+}
+
+fn validate(key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "resolution" => value.parse::<u16>().map(|_| ())
+            .map_err(|_| format!("{} is not a valid resolution, use a number like 720 or 0 for none", value)),
+        "proxy" => Proxy::parse(value).map(|_| ()),
+        "template" | "movie_template" => anime_organize::validate_template(value),
+        "sort" => value.parse::<SortOrder>().map(|_| ()),
+        "player" => anime_player::parse(value).map(|_| ()),
+        "trash" => match Path::new(value).is_absolute() {
+            true => Ok(()),
+            false => Err(format!("{} is not an absolute path", value)),
+        },
+        "noshow" | "organize" | "absolute_playlists" => match value {
+            "true" | "false" => Ok(()),
+            _ => Err(format!("{} must be true or false", key)),
+        },
+        _ => Err(format!("Unknown setting {}, known settings are {}", key,
+                         KEYS.iter().map(|(key, _)| *key).collect::<Vec<_>>().join(", "))),
+    }
+}
@@ -0,0 +1,95 @@
+extern crate fs2;
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::anime_part;
+
+const SAFETY_MARGIN: u64 = 512 * 1024 * 1024; // Warn when a batch would leave less than this free
+
+pub enum SpaceCheck {
+    Enough,
+    Tight { needed: u64, free: u64 },
+    NotEnough { needed: u64, free: u64 },
+}
+
+/// Bytes still missing for `filename` in `dir_path`, taking finished and partial downloads into account
+pub fn remaining_bytes(dir_path: &Path, filename: &str, size: u64) -> u64 {
+    let file_path = dir_path.join(filename);
+    if fs::metadata(&file_path).map(|meta| meta.len() == size).unwrap_or(false) {
+        return 0;
+    }
+
+    size.saturating_sub(anime_part::partial_len(&file_path))
+}
+
+/// Compares `needed` bytes against the free space of the filesystem holding `dir_path`.
+/// `dir_path` doesn't have to exist yet, its closest existing parent is checked instead
+pub fn check_free_space(dir_path: &Path, needed: u64) -> std::io::Result<SpaceCheck> {
+    let existing = dir_path.ancestors()
+        .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) // a relative path ends in the current folder
+        .find(|dir| dir.exists())
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Could not find a folder to check the free space of for {}", dir_path.display())))?;
+
+    let free = fs2::available_space(existing)?;
+    Ok(if needed > free {
+        SpaceCheck::NotEnough { needed, free }
+    } else if free - needed < SAFETY_MARGIN {
+        SpaceCheck::Tight { needed, free }
+    } else {
+        SpaceCheck::Enough
+    })
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.2} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn remaining_bytes_of_finished_partial_and_missing_files() {
+        let dir = std::env::temp_dir().join(format!("anime-cli-disk-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Show - 01.mkv"), vec![0; 100]).unwrap();
+        fs::write(anime_part::part_path(&dir.join("Show - 02.mkv")), vec![0; 30]).unwrap();
+
+        assert_eq!(remaining_bytes(&dir, "Show - 01.mkv", 100), 0);
+        assert_eq!(remaining_bytes(&dir, "Show - 02.mkv", 100), 70);
+        assert_eq!(remaining_bytes(&dir, "Show - 03.mkv", 100), 100);
+        assert_eq!(remaining_bytes(&dir, "Show - 02.mkv", 20), 0, "a .part can't need less than nothing");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn free_space_of_folders_still_to_be_made() {
+        let dir = std::env::temp_dir().join(format!("anime-cli-disk-missing-{}", process::id())).join("Show/Season 2");
+        assert!(check_free_space(&dir, 0).is_ok());
+        assert!(check_free_space(Path::new("not/made/yet"), 0).is_ok());
+        assert!(matches!(check_free_space(&dir, u64::MAX).unwrap(), SpaceCheck::NotEnough { .. }));
+    }
+
+    #[test]
+    fn bytes_at_unit_boundaries() {
+        assert_eq!(format_bytes(0), "0.00 B");
+        assert_eq!(format_bytes(1023), "1023.00 B");
+        assert_eq!(format_bytes(1024), "1.00 KB");
+        assert_eq!(format_bytes(1536), "1.50 KB");
+        assert_eq!(format_bytes(1024 * 1024), "1.00 MB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.00 GB");
+        assert_eq!(format_bytes(1 << 40), "1.00 TB");
+        assert_eq!(format_bytes(1 << 50), "1024.00 TB");
+    }
+}
@@ -1,22 +1,44 @@
-extern crate pbr;
 extern crate regex;
 extern crate rand;
+extern crate tokio;
 
-use std::io::{Read, Write, Error, ErrorKind};
-use std::net::{IpAddr, Ipv4Addr, Shutdown, TcpStream};
-use std::str::from_utf8;
-use std::{thread, time, fs};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr};
+use std::fs;
 use std::path::{PathBuf};
+use std::time::Duration;
 use rand::Rng;
 
 use lazy_static::lazy_static;
-use std::sync::mpsc::{Sender};
 use regex::Regex;
-use std::thread::sleep;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::{self, Instant};
+
+use crate::anime_disk::{self, SpaceCheck};
+use crate::anime_find::DCCPackage;
+use crate::anime_irc::{IRCLineReader, ReadError};
+use crate::anime_part;
+use crate::anime_proxy::{self, Proxy};
 
 const TIMEOUT_THRESHOLD: i8 = 5;
 const LOGIN_TIMEOUT_TICK: u64 = 500; // milliseconds before timeout counter ticks once
 const DL_TIMEOUT_TICK: u64 = 3000;
+const RESUME_TIMEOUT: u64 = 15000; // milliseconds a bot gets to answer DCC RESUME before we start over
+const QUEUE_RETRY_DELAY: u64 = 30000; // milliseconds before asking a bot whose queue was full again
+const QUEUE_RETRY_LIMIT: u32 = 10; // times a full queue is asked again before the pack is given up on
+const CONNECT_TIMEOUT: u64 = 30000; // milliseconds to reach the IRC server or a bot
+const STALL_TIMEOUT: u64 = 120000; // milliseconds a transfer may go without data before we give up on it
+const PROGRESS_INTERVAL: u64 = 250; // milliseconds between two progress events of a transfer
+const IRC_IDLE_TIMEOUT: u64 = 300000; // milliseconds without a line from the server before we consider it gone
+
+/// Where the bots the search API knows about hang out
+pub const IRC_SERVER: &str = "irc.rizon.net:6667";
+pub const IRC_CHANNEL: &str = "nibl";
+pub const IRC_NICKNAME: &str = "randomRustacean";
 
 lazy_static! {
     //static ref NICKNAME_REGEX: Regex = Regex::new(r#""#).unwrap();
@@ -27,15 +49,52 @@ lazy_static! {
     static ref MODE_REGEX: Regex = Regex::new(r#"MODE .* :\+.*"#).unwrap();
     static ref NOTICE_REGEX: Regex = Regex::new(r#"NOTICE .* You already requested"#).unwrap();
     static ref QUEUE_REGEX: Regex = Regex::new(r#".* queued too many .*"#).unwrap();
-    static ref RESUME_REGEX: Regex = Regex::new(r#"DCC ACCEPT .*"#).unwrap();
+    static ref DCC_ACCEPT_REGEX: Regex = Regex::new(r#"DCC ACCEPT (.+) (\d+) (\d+)"#).unwrap();
+    // Flipped once by `cancel`, every session and transfer watches it
+    static ref CANCELLED: (watch::Sender<bool>, watch::Receiver<bool>) = watch::channel(false);
+}
+
+/// What happens during a download session, sent in order over a single channel.
+/// `index` is the position of the pack in `IRCRequest::packages`
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DownloadEvent {
+    Connecting,
+    LoggedIn,
+    Requested { index: usize },
+    Queued { index: usize },
+    Started { index: usize, filename: String, size: u64 },
+    Progress { index: usize, bytes: u64, rate: f64 },
+    Resumed { index: usize, offset: u64 },
+    Verified { index: usize },
+    Failed { index: usize, reason: String },
+    Finished { index: usize },
+    Notice { message: String },
+    AllDone,
 }
 
+/// One IRC session fetching `packages[i]` from `bot[i]`, in order
 pub struct IRCRequest {
     pub server: String,
     pub channel: String,
     pub nickname: String,
     pub bot: Vec<String>,
     pub packages: Vec<String>,
+    pub proxy: Option<Proxy>,
+}
+
+impl IRCRequest {
+    /// Request for packages found with `anime_find`, on the server and channel their bots live on
+    pub fn for_packages(packages: &[DCCPackage], proxy: Option<Proxy>) -> IRCRequest {
+        IRCRequest {
+            server: IRC_SERVER.to_string(),
+            channel: IRC_CHANNEL.to_string(),
+            nickname: IRC_NICKNAME.to_string(),
+            bot: packages.iter().map(|package| package.bot.clone()).collect(),
+            packages: packages.iter().map(|package| package.number.to_string()).collect(),
+            proxy,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -43,221 +102,352 @@ struct DCCSend {
     filename: String,
     ip: IpAddr,
     port: String,
-    file_size: usize,
+    file_size: u64,
+}
+
+/// Everything a transfer task needs to fetch one pack
+struct DownloadJob {
+    index: usize,
+    request: DCCSend,
+    bot: String,
+    pack: String,
+    dir_path: PathBuf,
+    proxy: Option<Proxy>,
+}
+
+struct DCCAccept {
+    port: String,
+    position: u64,
 }
 
+struct PendingResume {
+    position: u64,
+    deadline: Instant,
+}
+
+/// Write side of the IRC session, lines from the server are read with an `IRCLineReader`
 struct IRCConnection {
-    socket: TcpStream,
-    partial_msg: String,
+    writer: OwnedWriteHalf,
+}
+
+/// Asks every running transfer and the IRC session to wind down, used by the Ctrl-C handler
+pub fn cancel() {
+    CANCELLED.0.send_replace(true);
+}
+
+pub fn is_cancelled() -> bool {
+    *CANCELLED.1.borrow()
+}
+
+/// Lets downloads run again after `cancel`, for callers that carry on after cancelling one
+pub fn reset_cancel() {
+    CANCELLED.0.send_replace(false);
+}
+
+/// Resolves once `cancel` has been called
+async fn cancelled() {
+    let mut receiver = CANCELLED.1.clone();
+    let _ = receiver.wait_for(|cancelled| *cancelled).await;
 }
 
 impl IRCConnection {
-    fn read_message(&mut self) -> Option<String> {
-        let mut buffer = [0; 4];
-        let count = match self.socket.read(&mut buffer[..]) {
-            Ok(a) => a,
-            Err(_) => return Some(String::from("Error"))
-        };
-        self.partial_msg.push_str(from_utf8(&buffer[..count]).unwrap_or_default());
-        //println!("{}", self.message_builder);
-        if self.partial_msg.contains('\n') {
-            let endline_offset = self.partial_msg.find('\n').unwrap() + 1;
-            let message = self.partial_msg.get(..endline_offset).unwrap().to_string();
-            self.partial_msg.replace_range(..endline_offset, "");
-            Some(message)
-        } else {
-            None
-        }
+    async fn send(&mut self, command: &str) -> std::io::Result<()> {
+        self.writer.write_all(format!("{}\r\n", command).as_bytes()).await
     }
 }
 
-pub fn connect_and_download(request: IRCRequest, channel_senders: Vec<Sender<i64>>, status_bar_sender: Sender<String>, dir_path: PathBuf) -> Result<(), std::io::Error> {
-    status_bar_sender.send(format!("Connecting to Rizon...")).unwrap();
+/// Downloads every pack of `request` into `dir_path`, calling `on_event` on this thread for each event.
+/// Runs its own runtime and returns once the session is over, `DownloadEvent::AllDone` being the last event on success
+pub fn download<F: FnMut(DownloadEvent)>(request: IRCRequest, dir_path: PathBuf, mut on_event: F) -> Result<(), std::io::Error> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async move {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let session = tokio::spawn(connect_and_download(request, sender, dir_path));
+        while let Some(event) = receiver.recv().await { // ends once the session and its transfers dropped their senders
+            on_event(event);
+        }
 
-    let mut download_handles = Vec::new();
-    let mut has_joined = false;
-    let stream = log_in(&request).unwrap();
-    let mut connection : IRCConnection = IRCConnection { socket: stream, partial_msg: "".to_string()};
+        match session.await {
+            Ok(result) => result,
+            Err(_) => Err(Error::new(ErrorKind::Other, "Download session crashed")),
+        }
+    })
+}
+
+/// Same as `download`, for callers already on a tokio runtime. Events go to `events`
+pub async fn connect_and_download(request: IRCRequest, events: mpsc::UnboundedSender<DownloadEvent>, dir_path: PathBuf) -> Result<(), std::io::Error> {
+    let _ = events.send(DownloadEvent::Connecting);
 
-    let mut next = time::Instant::now() + time::Duration::from_millis(LOGIN_TIMEOUT_TICK);
+    let (mut connection, mut reader) = log_in(&request).await?;
+    let idle_timeout = Duration::from_millis(IRC_IDLE_TIMEOUT);
+    let channel_join_cmd = format!("JOIN #{}", request.channel);
+    let mut login_tick = time::interval_at(
+        Instant::now() + Duration::from_millis(LOGIN_TIMEOUT_TICK), Duration::from_millis(LOGIN_TIMEOUT_TICK));
     let mut timeout_counter = 0;
-    status_bar_sender.send(format!("Logging into Rizon...")).unwrap();
-    while !has_joined {
-        let message = connection.read_message();
-        let now = time::Instant::now();
-        if message.is_some() {
-            let msg = &message.unwrap();
-            //println!("{}",msg);
-            if "Error".eq(msg) {
-                return Err(Error::new(ErrorKind::Other, String::from("Error reading TcpStream")))
-            }
-            if PING_REGEX.is_match(msg) {
-                let pong = msg.replace("PING", "PONG");
-                connection.socket.write(pong.as_bytes()).unwrap();
-                if !has_joined {
-                    let channel_join_cmd = format!("JOIN #{}\r\n", request.channel);
-                    connection.socket.write(channel_join_cmd.as_bytes()).unwrap();
+    loop {
+        tokio::select! {
+            _ = cancelled() => {
+                quit(&mut connection, "Cancelled").await;
+                for index in 0..request.packages.len() {
+                    let _ = events.send(DownloadEvent::Failed { index, reason: "Cancelled".to_string() });
                 }
-            }
-            if MODE_REGEX.is_match(msg) {
-                if !has_joined {
-                    let channel_join_cmd = format!("JOIN #{}\r\n", request.channel);
-                    connection.socket.write(channel_join_cmd.as_bytes()).unwrap();
+                let _ = events.send(DownloadEvent::AllDone);
+                return Ok(());
+            },
+            message = reader.read_line(idle_timeout) => {
+                let msg = match message {
+                    Ok(msg) => msg,
+                    Err(ReadError::TooLong) => continue, // nothing we wait for comes in lines that long
+                    Err(e) => {
+                        let e = Error::from(e);
+                        return Err(Error::new(e.kind(), format!("{} while logging in", e)));
+                    },
+                };
+                if PING_REGEX.is_match(&msg) {
+                    connection.send(&msg.replace("PING", "PONG")).await?;
+                    connection.send(&channel_join_cmd).await?;
                 }
-            }
-            if JOIN_REGEX.is_match(msg) {
-                has_joined = true;
-            }
-        } else {
-            if now >= next {
-                let channel_join_cmd = format!("JOIN #{}\r\n", request.channel);
-                connection.socket.write(channel_join_cmd.as_bytes()).unwrap();
-                next = now + time::Duration::from_millis(LOGIN_TIMEOUT_TICK);
+                if MODE_REGEX.is_match(&msg) {
+                    connection.send(&channel_join_cmd).await?;
+                }
+                if JOIN_REGEX.is_match(&msg) {
+                    break;
+                }
+            },
+            _ = login_tick.tick() => {
+                connection.send(&channel_join_cmd).await?;
                 timeout_counter += 1;
                 if timeout_counter > TIMEOUT_THRESHOLD {
-                    return Err(Error::new(ErrorKind::Other, String::from("Timed out logging in")))
+                    return Err(Error::new(ErrorKind::TimedOut, String::from("Timed out logging in")))
                 }
-
-            }
+            },
         }
-        //thread::sleep(time::Duration::from_micros(10));
     }
 
-    status_bar_sender.send(format!("Connected")).unwrap();
+    let _ = events.send(DownloadEvent::LoggedIn);
 
     let mut i = 0;
     let mut requests : Vec<DCCSend> = vec![];
-    let mut resume = false;
-    let mut wait = false;
-    let mut received_reply;
-    while download_handles.len() < request.packages.len() && timeout_counter <= TIMEOUT_THRESHOLD {
-        if wait {
-            //wait til a previous package is downloaded then proceed
-            let f = fs::File::open(&requests[i-1].filename)?;
-            let meta = f.metadata()?;
-            while meta.len() < requests[i-1].file_size as u64 {
-                sleep(time::Duration::from_secs(1));
-            }
-            wait = false;
-        }
+    let mut finished: Vec<bool> = vec![];
+    let mut transfers = JoinSet::new();
+    let mut pending_resume: Option<PendingResume> = None;
+    let mut queued = false; // bot won't take more requests until our previous pack is done
+    let mut needs_request = true;
+    let mut gave_up = false;
+    let mut queue_retry: Option<Instant> = None; // when to ask a bot with a full queue again
+    let mut queue_retries = 0;
+    let mut next_tick = Instant::now();
+    let job = |index: usize, dcc_send: DCCSend| DownloadJob {
+        index,
+        bot: request.bot[index].clone(),
+        pack: request.packages[index].clone(),
+        request: dcc_send,
+        dir_path: dir_path.clone(),
+        proxy: request.proxy.clone(),
+    };
+    while i < request.packages.len() && !gave_up {
         let package_bot = &request.bot[i];
         let package_number = &request.packages[i];
-        if !resume {
-            let xdcc_send_cmd =
-                format!("PRIVMSG {} :xdcc send #{}\r\n", package_bot, package_number);
-            connection.socket.write(xdcc_send_cmd.as_bytes()).unwrap();
+        if needs_request && !queued && queue_retry.is_none() {
+            if pending_resume.is_none() {
+                connection.send(&format!("PRIVMSG {} :xdcc send #{}", package_bot, package_number)).await?;
+                let _ = events.send(DownloadEvent::Requested { index: i });
+            }
+            needs_request = false;
+            timeout_counter = 0;
+            next_tick = Instant::now() + Duration::from_millis(DL_TIMEOUT_TICK);
         }
+        //postpone the timeout if currently downloading, if bot doesn't care to give queue message
+        //some batch xdcc bots will add you into a queue but won't send more than x number of dcc sends
+        let dl_in_progress = i > 0 && !finished[i - 1];
+        let resume_deadline = pending_resume.as_ref().map(|resume| resume.deadline);
 
-        next = time::Instant::now() + time::Duration::from_millis(DL_TIMEOUT_TICK);
-        timeout_counter = 0;
-        received_reply = false;
-        while !received_reply && timeout_counter <= TIMEOUT_THRESHOLD {
-            let message = connection.read_message();
-            let now = time::Instant::now();
-            if message.is_some() {
-                let msg = &message.unwrap();
-                //println!("{}",msg);
-                if "Error".eq(msg) {
-                    return Err(Error::new(ErrorKind::Other, String::from(format!("Error reading TcpStream on pack {}", package_number))))
+        tokio::select! {
+            _ = cancelled() => break,
+            message = reader.read_line(idle_timeout) => {
+                let msg = match message {
+                    Ok(msg) => msg,
+                    Err(ReadError::TooLong) => continue,
+                    Err(e) => {
+                        let e = Error::from(e);
+                        return Err(Error::new(e.kind(), format!("{} on pack {}", e, package_number)));
+                    },
+                };
+                if PING_REGEX.is_match(&msg) {
+                    connection.send(&msg.replace("PING", "PONG")).await?;
                 }
-                if DCC_SEND_REGEX.is_match(msg) {
-                    let request = parse_dcc_send(msg);
-                    requests.push(request);
-                    status_bar_sender.send(format!("Now downloading {}", &requests[i].filename)).unwrap();
-                    if std::path::Path::new(&requests[i].filename).exists() {
-                        status_bar_sender.send(format!("Found an existing {}", &requests[i].filename)).unwrap();
-                        let f = fs::File::open(&requests[i].filename)?;
-                        let meta = f.metadata()?;
-                        if (meta.len() as usize) < requests[i].file_size {
-                            let xdcc_resume_cmd =
-                                format!("PRIVMSG {} :\x01DCC RESUME \"{}\" {} {}\x01\r\n", package_bot, &requests[i].filename, &requests[i].port, meta.len());
-                            connection.socket.write(xdcc_resume_cmd.as_bytes()).unwrap();
-                            resume = true;
+                if DCC_SEND_REGEX.is_match(&msg) {
+                    queue_retries = 0;
+                    let dcc_send = parse_dcc_send(&msg);
+                    let file_path = dir_path.join(&dcc_send.filename);
+                    // Transfers still running need their room too
+                    let in_flight: u64 = requests.iter().zip(finished.iter())
+                        .filter(|(_, done)| !**done)
+                        .map(|(req, _)| anime_disk::remaining_bytes(&dir_path, &req.filename, req.file_size))
+                        .sum();
+                    let needed = in_flight + anime_disk::remaining_bytes(&dir_path, &dcc_send.filename, dcc_send.file_size);
+                    match anime_disk::check_free_space(&dir_path, needed) {
+                        Ok(SpaceCheck::NotEnough { needed, free }) => {
+                            let _ = events.send(DownloadEvent::Failed {
+                                index: i,
+                                reason: format!("Not enough disk space ({} needed, {} free)", anime_disk::format_bytes(needed), anime_disk::format_bytes(free)),
+                            });
+                            connection.send(&format!("PRIVMSG {} :\x01XDCC CANCEL\x01", package_bot)).await?;
+                            requests.push(dcc_send);
+                            finished.push(true);
+                            i += 1;
+                            needs_request = true;
+                            continue;
+                        },
+                        Ok(SpaceCheck::Tight { free, .. }) => {
+                            let _ = events.send(DownloadEvent::Notice { message: format!("Disk space is running low, {} free", anime_disk::format_bytes(free)) });
+                        },
+                        _ => {}
+                    }
+                    match anime_part::find_resumable(&file_path, dcc_send.file_size) {
+                        Some(position) => {
+                            let _ = events.send(DownloadEvent::Notice { message: format!("Found a partial download of {}, asking to resume at byte {}", &dcc_send.filename, position) });
+                            connection.send(&format!("PRIVMSG {} :\x01DCC RESUME \"{}\" {} {}\x01", package_bot, &dcc_send.filename, &dcc_send.port, position)).await?;
+                            pending_resume = Some(PendingResume {
+                                position,
+                                deadline: Instant::now() + Duration::from_millis(RESUME_TIMEOUT),
+                            });
+                            requests.push(dcc_send);
+                        },
+                        None => {
+                            requests.push(dcc_send);
+                            finished.push(false);
+                            transfers.spawn(run_transfer(job(i, requests[i].clone()), events.clone()));
+                            i += 1;
                         }
                     }
-                    if !resume {
-                        let req = requests[i].clone();
-                        let sender = channel_senders[i].clone();
-                        let path = dir_path.clone();
-                        let handle = thread::spawn(move || {
-                            download_file(req, sender, path).unwrap();
-                        });
-                        download_handles.push(handle);
+                    needs_request = true;
+                }
+                if pending_resume.is_some() && DCC_ACCEPT_REGEX.is_match(&msg) {
+                    let resume = pending_resume.take().unwrap();
+                    let accept = parse_dcc_accept(&msg);
+                    let file_path = dir_path.join(&requests[i].filename);
+                    if accept.port != requests[i].port || accept.position > resume.position {
+                        // We can't trust this transfer to line up with what's on disk, ask for the whole file again
+                        let _ = events.send(DownloadEvent::Notice { message: format!("Bot accepted the resume of {} at byte {} instead of {}, starting over", requests[i].filename, accept.position, resume.position) });
+                        anime_part::discard(&file_path);
+                        connection.send(&format!("PRIVMSG {} :\x01XDCC CANCEL\x01", package_bot)).await?;
+                        requests.pop();
+                    } else {
+                        if accept.position < resume.position {
+                            anime_part::truncate(&file_path, accept.position)?;
+                        }
+                        let _ = events.send(DownloadEvent::Resumed { index: i, offset: accept.position });
+                        finished.push(false);
+                        transfers.spawn(run_transfer(job(i, requests[i].clone()), events.clone()));
                         i += 1;
                     }
-                    received_reply = true;
+                    needs_request = true;
                 }
-                if resume && RESUME_REGEX.is_match(msg){
-                    status_bar_sender.send(format!("Attempting to resume download for {}", requests[i].filename)).unwrap();
-                    let req = requests[i].clone();
-                    let sender = channel_senders[i].clone();
-                    let path = dir_path.clone();
-                    let handle = thread::spawn(move || {
-                        download_file(req, sender, path).unwrap();
-                    });
-                    download_handles.push(handle);
-                    i += 1;
-                    resume = false;
-                    received_reply = true;
+                if QUEUE_REGEX.is_match(&msg) {
+                    //bot tells you that you can't queue up a new file, ask again once the previous pack is done
+                    let _ = events.send(DownloadEvent::Queued { index: i });
+                    queued = dl_in_progress;
+                    needs_request = true;
+                    if !dl_in_progress { // nothing of ours to wait for, so wait a while before asking again
+                        queue_retries += 1;
+                        if queue_retries > QUEUE_RETRY_LIMIT {
+                            let _ = events.send(DownloadEvent::Failed { index: i, reason: "The bot's queue stayed full".to_string() });
+                            finished.push(true);
+                            gave_up = true;
+                        } else {
+                            let _ = events.send(DownloadEvent::Notice { message: format!("({}/{}) The bot's queue is full, asking again for pack {} in {}s",
+                                                                                          queue_retries, QUEUE_RETRY_LIMIT, package_number, QUEUE_RETRY_DELAY / 1000) });
+                            queue_retry = Some(Instant::now() + Duration::from_millis(QUEUE_RETRY_DELAY));
+                        }
+                    }
                 }
-                if QUEUE_REGEX.is_match(msg) {
-                    //bot tells you that you can't queue up a new file
-                    wait = true;
-                    received_reply = true;
+                if NOTICE_REGEX.is_match(&msg) {
+                    let _ = events.send(DownloadEvent::Notice { message: format!("A previous request was made for pack {}, attempting to cancel and retry", package_number) });
+                    connection.send(&format!("PRIVMSG {} :xdcc remove #{}", package_bot, package_number)).await?;
+                    connection.send(&format!("PRIVMSG {} :\x01XDCC CANCEL\x01", package_bot)).await?;
+                    needs_request = true;
                 }
-                if NOTICE_REGEX.is_match(msg) {
-                    status_bar_sender.send(format!("A previous request was made for pack {}, attempting to cancel and retry", package_number)).unwrap();
-                    let xdcc_remove_cmd =
-                        format!("PRIVMSG {} :xdcc remove #{}\r\n", package_bot, package_number);
-                    connection.socket.write(xdcc_remove_cmd.as_bytes()).unwrap();
-                    let xdcc_cancel_cmd =
-                        format!("PRIVMSG {} :\x01XDCC CANCEL\x01\r\n", package_bot);
-                    connection.socket.write(xdcc_cancel_cmd.as_bytes()).unwrap();
-                    received_reply = true;
+            },
+            Some(transfer) = transfers.join_next() => {
+                if let Ok(index) = transfer {
+                    finished[index] = true;
                 }
-            } else {
-                //postpone the timeout if currently downloading, if bot doesn't care to give queue message
-                //some batch xdcc bots will add you into a queue but won't send more than x number of dcc sends
-                let mut dl_in_progress = false;
-                if (i > requests.len()) && std::path::Path::new(&requests[i-1].filename).exists() {
-                    let f = fs::File::open(&requests[i - 1].filename)?;
-                    let meta = f.metadata()?;
-
-                    if !meta.len() < requests[i - 1].file_size as u64 {
-                        dl_in_progress = true;
-                    }
+                if i > 0 && finished[i - 1] {
+                    queued = false;
+                    // Start counting again now that the bot has a free slot for us
+                    next_tick = Instant::now() + Duration::from_millis(DL_TIMEOUT_TICK);
                 }
-                if now >= next && !dl_in_progress {
-                    next = now + time::Duration::from_millis(DL_TIMEOUT_TICK);
-                    timeout_counter += 1;
-                    status_bar_sender.send(format!("({}/{}) Waiting on dcc send reply for pack {}...", timeout_counter, TIMEOUT_THRESHOLD, package_number)).unwrap();
-                    if timeout_counter > TIMEOUT_THRESHOLD {
-                        status_bar_sender.send(format!("Timed out receiving dcc send for pack {}", package_number)).unwrap();
-                    }
+            },
+            _ = time::sleep_until(resume_deadline.unwrap_or(next_tick)), if resume_deadline.is_some() => {
+                // The bot ignored our resume, its original offer still stands so take the whole file instead
+                pending_resume = None;
+                let _ = events.send(DownloadEvent::Notice { message: format!("No answer to the resume of {}, downloading it from the start", requests[i].filename) });
+                anime_part::discard(&dir_path.join(&requests[i].filename));
+                finished.push(false);
+                transfers.spawn(run_transfer(job(i, requests[i].clone()), events.clone()));
+                i += 1;
+                needs_request = true;
+            },
+            _ = time::sleep_until(queue_retry.unwrap_or(next_tick)), if queue_retry.is_some() => queue_retry = None,
+            _ = time::sleep_until(next_tick), if !queued && !dl_in_progress && resume_deadline.is_none() && queue_retry.is_none() => {
+                next_tick = Instant::now() + Duration::from_millis(DL_TIMEOUT_TICK);
+                timeout_counter += 1;
+                let _ = events.send(DownloadEvent::Notice { message: format!("({}/{}) Waiting on dcc send reply for pack {}...", timeout_counter, TIMEOUT_THRESHOLD, package_number) });
+                if timeout_counter > TIMEOUT_THRESHOLD {
+                    let _ = events.send(DownloadEvent::Failed { index: i, reason: "Timed out waiting for the DCC send".to_string() });
+                    finished.push(true);
+                    gave_up = true;
                 }
-            }
+            },
         }
     }
 
-    connection.socket
-        .write("QUIT :my job is done here!\r\n".as_bytes())
-        .unwrap();
-    connection.socket.shutdown(Shutdown::Both).unwrap();
-    download_handles
-        .into_iter()
-        .for_each(|handle| handle.join().unwrap());
-    status_bar_sender.send("Success".to_string()).unwrap();
+    if is_cancelled() {
+        let _ = events.send(DownloadEvent::Notice { message: "Cancelling pending requests...".to_string() });
+        // Packs we asked for but haven't fully received, the one being negotiated included
+        let pending = (0..request.packages.len().min(i + 1))
+            .filter(|&pack| pack >= finished.len() || !finished[pack]);
+        let mut cancelled_bots: Vec<&String> = vec![];
+        for pack in pending {
+            let _ = connection.send(&format!("PRIVMSG {} :xdcc remove #{}", request.bot[pack], request.packages[pack])).await;
+            if !cancelled_bots.contains(&&request.bot[pack]) {
+                let _ = connection.send(&format!("PRIVMSG {} :\x01XDCC CANCEL\x01", request.bot[pack])).await;
+                cancelled_bots.push(&request.bot[pack]);
+            }
+        }
+        quit(&mut connection, "Cancelled").await;
+    } else {
+        quit(&mut connection, "my job is done here!").await;
+    }
+    // Packs that never got a transfer, because we stopped or gave up early
+    for index in finished.len()..request.packages.len() {
+        let reason = if is_cancelled() { "Cancelled" } else { "Not requested after an earlier pack was given up on" };
+        let _ = events.send(DownloadEvent::Failed { index, reason: reason.to_string() });
+    }
+    while transfers.join_next().await.is_some() {}
+    let _ = events.send(DownloadEvent::AllDone);
     Ok(())
 }
 
-fn log_in(request: &IRCRequest) -> Result<TcpStream, std::io::Error> {
-    let mut stream = TcpStream::connect(&request.server)?;
-    let mut rng = rand::thread_rng();
-    let rng_num: u16 = rng.gen();
+async fn quit(connection: &mut IRCConnection, reason: &str) {
+    let _ = connection.send(&format!("QUIT :{}", reason)).await;
+    let _ = connection.writer.shutdown().await;
+}
+
+async fn log_in(request: &IRCRequest) -> Result<(IRCConnection, IRCLineReader<OwnedReadHalf>), std::io::Error> {
+    let stream = match time::timeout(Duration::from_millis(CONNECT_TIMEOUT), anime_proxy::connect(&request.server, &request.proxy)).await {
+        Ok(stream) => stream?,
+        Err(_) => return Err(Error::new(ErrorKind::TimedOut, format!("Timed out connecting to {}", request.server))),
+    };
+    let rng_num: u16 = rand::thread_rng().gen();
     let rng_nick = format!("{}{}", request.nickname, rng_num);
-    stream.write(format!("NICK {}\r\n", rng_nick).as_bytes())?;
-    stream.write(format!("USER {} 0 * {}\r\n", rng_nick, rng_nick).as_bytes())?;
-    Ok(stream)
+
+    let (reader, writer) = stream.into_split();
+    let mut connection = IRCConnection { writer };
+    connection.send(&format!("NICK {}", rng_nick)).await?;
+    connection.send(&format!("USER {} 0 * {}", rng_nick, rng_nick)).await?;
+    Ok((connection, IRCLineReader::new(reader)))
 }
 
 fn parse_dcc_send(message: &String) -> DCCSend {
@@ -267,34 +457,131 @@ fn parse_dcc_send(message: &String) -> DCCSend {
         filename: captures[1].to_string().replace("\"",""),
         ip: IpAddr::V4(Ipv4Addr::from(ip_number)),
         port: captures[3].to_string(),
-        file_size: captures[4].parse::<usize>().unwrap(),
+        file_size: captures[4].parse::<u64>().unwrap(),
     }
 }
 
-fn download_file(
-    request: DCCSend,
-    sender: Sender<i64>,
-    dir_path: PathBuf) -> std::result::Result<(), std::io::Error> {
+fn parse_dcc_accept(message: &String) -> DCCAccept {
+    let captures = DCC_ACCEPT_REGEX.captures(&message).unwrap();
+    DCCAccept {
+        port: captures[2].to_string(),
+        position: captures[3].parse::<u64>().unwrap(),
+    }
+}
+
+/// Runs one transfer, reporting a failure as an event. Resolves to the pack's index once it's over
+async fn run_transfer(job: DownloadJob, events: mpsc::UnboundedSender<DownloadEvent>) -> usize {
+    let index = job.index;
+    // On a task of its own, so a transfer that panics still ends up reported and its slot marked finished
+    let transfer_events = events.clone();
+    let failure = match tokio::spawn(async move { download_file(job, &transfer_events).await }).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(e) => Some(format!("Transfer stopped unexpectedly: {}", e)),
+    };
+    if let Some(reason) = failure {
+        let _ = events.send(DownloadEvent::Failed { index, reason });
+    }
+    index
+}
+
+async fn download_file(job: DownloadJob, events: &mpsc::UnboundedSender<DownloadEvent>) -> std::result::Result<(), std::io::Error> {
+    let DownloadJob { index, request, bot, pack, dir_path, proxy } = job;
     let file_path = dir_path.join(&request.filename);
-    let mut file =  match fs::OpenOptions::new().append(true).open(file_path.clone()) {
-        Ok(existing_file) => existing_file,
-        Err(_) => fs::File::create(file_path.clone())?
+    let expected_size = request.file_size;
+    let _ = events.send(DownloadEvent::Started { index, filename: request.filename.clone(), size: expected_size });
+    if fs::metadata(&file_path).map(|meta| meta.len() == expected_size).unwrap_or(false) { // Finished on an earlier run
+        let _ = events.send(DownloadEvent::Progress { index, bytes: expected_size, rate: 0.0 });
+        let _ = events.send(DownloadEvent::Finished { index });
+        return Ok(());
+    }
+
+    anime_part::write_meta(&file_path, &anime_part::PartMeta { expected_size, bot, pack })?;
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(anime_part::part_path(&file_path)).await?;
+    let progress = file.metadata().await?.len();
+
+    let received = if progress < expected_size {
+        receive(&request, &proxy, &mut file, progress, index, events).await
+    } else {
+        Ok(())
     };
-    let mut stream = TcpStream::connect(format!("{}:{}", request.ip, request.port))?;
-    let mut buffer = [0; 4096];
-    let meta = file.metadata()?;
-    let mut progress = meta.len() as usize;
+    // Whatever made it to disk stays in the .part so the next run can resume from it
+    file.flush().await?;
+    drop(file);
+    received?;
+    if is_cancelled() {
+        return Err(Error::new(ErrorKind::Interrupted, "Cancelled, partial download kept"));
+    }
 
-    while progress < request.file_size {
-        let count = stream.read(&mut buffer[..])?;
-        file.write(&mut buffer[..count])?;
-        progress += count;
-        sender.send(progress as i64).unwrap();
+    // Checking the CRC reads the whole episode, keep that off the runtime's threads
+    let verify_path = file_path.clone();
+    match tokio::task::spawn_blocking(move || anime_part::finalize(&verify_path, expected_size)).await {
+        Ok(result) => result?,
+        Err(_) => return Err(Error::new(ErrorKind::Other, "Verification crashed")),
     }
+    let _ = events.send(DownloadEvent::Verified { index });
+    let _ = events.send(DownloadEvent::Finished { index });
+    Ok(())
+}
 
-    sender.send(-1).unwrap();
-    stream.shutdown(Shutdown::Both)?;
-    file.flush()?;
+/// Appends the bot's data to `file` until the transfer is complete, the bot hangs up or we're cancelled
+async fn receive(
+    request: &DCCSend,
+    proxy: &Option<Proxy>,
+    file: &mut tokio::fs::File,
+    mut progress: u64,
+    index: usize,
+    events: &mpsc::UnboundedSender<DownloadEvent>) -> std::result::Result<(), std::io::Error> {
+    let address = format!("{}:{}", request.ip, request.port);
+    let mut stream = tokio::select! {
+        stream = time::timeout(Duration::from_millis(CONNECT_TIMEOUT), anime_proxy::connect(&address, proxy)) => match stream {
+            Ok(stream) => stream?,
+            Err(_) => return Err(Error::new(ErrorKind::TimedOut, format!("Timed out connecting to the bot at {}", address))),
+        },
+        _ = cancelled() => return Ok(()),
+    };
+    let mut buffer = vec![0; 64 * 1024];
+    let mut last_report = Instant::now();
+    let mut last_progress = progress;
 
+    while progress < request.file_size {
+        let count = tokio::select! {
+            read = time::timeout(Duration::from_millis(STALL_TIMEOUT), stream.read(&mut buffer[..])) => match read {
+                Ok(Ok(0)) => break, // bot closed the connection
+                Ok(Ok(count)) => count,
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Err(Error::new(ErrorKind::TimedOut, format!("No data from the bot for {} seconds", STALL_TIMEOUT / 1000))),
+            },
+            _ = cancelled() => break,
+        };
+        file.write_all(&buffer[..count]).await?;
+        progress += count as u64;
+
+        let elapsed = last_report.elapsed();
+        if elapsed >= Duration::from_millis(PROGRESS_INTERVAL) || progress >= request.file_size {
+            let rate = (progress - last_progress) as f64 / elapsed.as_secs_f64().max(0.001);
+            let _ = events.send(DownloadEvent::Progress { index, bytes: progress, rate });
+            last_report = Instant::now();
+            last_progress = progress;
+        }
+    }
+    let _ = stream.shutdown().await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancel_and_reset() {
+        reset_cancel();
+        assert!(!is_cancelled());
+        let waiting = tokio::spawn(cancelled());
+        cancel();
+        assert!(is_cancelled());
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+        reset_cancel();
+        assert!(!is_cancelled(), "downloads can run again");
+    }
+}
@@ -0,0 +1,326 @@
+extern crate serde;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Serialize, Serializer};
+
+lazy_static! {
+    static ref NUMBER_REGEX: Regex = Regex::new(r"^(\d+)(\.5)?$").unwrap();
+    static ref SPECIAL_REGEX: Regex = Regex::new(r"(?i)^(OVA|OAD|ONA|SP|SPECIAL)\s*-?\s*(\d+)?$").unwrap();
+    static ref VERSION_REGEX: Regex = Regex::new(r"(?i)^(.*?)\s*v(\d+)$").unwrap();
+}
+
+/// One episode of a show, as asked for on the command line or read from a release name
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Episode {
+    Regular(u32),
+    /// Recaps and extras numbered between two regular episodes, `Half(12)` is episode 12.5
+    Half(u32),
+    /// OVA, OAD, ONA and SP episodes, which have their own numbering
+    Special { kind: String, number: Option<u32> },
+}
+
+impl Episode {
+    /// Number the search API can filter on, half episodes and specials have none
+    pub fn search_number(&self) -> Option<u32> {
+        match self {
+            Episode::Regular(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn is_special(&self) -> bool {
+        match self {
+            Episode::Special { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Episode {
+    type Err = String;
+
+    /// `12`, `12.5`, `OVA`, `OVA 2`, `SP1` or `Special 3`
+    fn from_str(text: &str) -> Result<Episode, String> {
+        let text = text.trim();
+        if let Some(caps) = NUMBER_REGEX.captures(text) {
+            let number = caps[1].parse().map_err(|_| format!("{} is too large for an episode number", &caps[1]))?;
+            return Ok(if caps.get(2).is_some() { Episode::Half(number) } else { Episode::Regular(number) });
+        }
+        if let Some(caps) = SPECIAL_REGEX.captures(text) {
+            let kind = match caps[1].to_uppercase().as_str() {
+                "SPECIAL" => "SP".to_string(),
+                kind => kind.to_string(),
+            };
+            return Ok(Episode::Special { kind, number: caps.get(2).and_then(|n| n.as_str().parse().ok()) });
+        }
+        Err(format!("{} is not an episode, try e.g. 12, 12.5, OVA or SP1", text))
+    }
+}
+
+impl fmt::Display for Episode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Episode::Regular(n) => write!(f, "{}", n),
+            Episode::Half(n) => write!(f, "{}.5", n),
+            Episode::Special { kind, number: Some(n) } => write!(f, "{}{}", kind, n),
+            Episode::Special { kind, number: None } => write!(f, "{}", kind),
+        }
+    }
+}
+
+// Regular and half episodes in airing order, then specials
+impl Ord for Episode {
+    fn cmp(&self, other: &Episode) -> Ordering {
+        fn key(episode: &Episode) -> (bool, u64, &str, Option<u32>) {
+            match episode {
+                Episode::Regular(n) => (false, *n as u64 * 2, "", None),
+                Episode::Half(n) => (false, *n as u64 * 2 + 1, "", None),
+                Episode::Special { kind, number } => (true, 0, kind, *number),
+            }
+        }
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for Episode {
+    fn partial_cmp(&self, other: &Episode) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Numbers stay numbers in JSON output, specials become strings
+impl Serialize for Episode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Episode::Regular(n) => serializer.serialize_u32(*n),
+            Episode::Half(n) => serializer.serialize_f64(*n as f64 + 0.5),
+            special => serializer.collect_str(special),
+        }
+    }
+}
+
+/// An episode to look for, with the release version it has to be if one was asked for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wanted {
+    pub episode: Episode,
+    pub version: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    All,
+    Latest,
+    Single(Episode),
+    /// Regular episodes from the first number up to the second, or up to the latest one
+    Range(u32, Option<u32>),
+}
+
+/// Which episodes to get, parsed from e.g. `1-5,8,10-`, `latest`, `all`, `12.5`, `OVA` or `3v2`.
+/// A lone `v2` applies that version to everything without one of its own
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpisodeSpec {
+    items: Vec<(Item, Option<u8>)>,
+}
+
+impl EpisodeSpec {
+    /// Regular episodes `first` to `last`, what `-e` and `-b` used to mean
+    pub fn range(first: u32, last: u32) -> EpisodeSpec {
+        EpisodeSpec { items: vec![(Item::Range(first, Some(last.max(first))), None)] }
+    }
+
+    /// Whether the spec can only be resolved against the episodes that are out there,
+    /// e.g. `latest` or `10-`. Half episodes and specials can't be searched for by number either
+    pub fn needs_listing(&self) -> bool {
+        self.items.iter().any(|(item, _)| match item {
+            Item::All | Item::Latest | Item::Range(_, None) => true,
+            Item::Single(episode) => episode.search_number().is_none(),
+            Item::Range(_, Some(_)) => false,
+        })
+    }
+
+    /// The episodes asked for in airing order, `available` is what a search without an episode found.
+    /// Episodes named explicitly are kept even if they weren't found, so the user hears about them
+    pub fn select(&self, available: &[Episode]) -> Vec<Wanted> {
+        let latest = available.iter().filter(|e| !e.is_special()).max();
+        let last_regular = available.iter().filter_map(Episode::search_number).max();
+
+        let mut wanted: Vec<Wanted> = vec![];
+        for (item, version) in &self.items {
+            let episodes: Vec<Episode> = match item {
+                Item::All => available.to_vec(),
+                Item::Latest => latest.into_iter().cloned().collect(),
+                Item::Single(episode) => vec![episode.clone()],
+                Item::Range(first, last) => match last.or(last_regular) {
+                    Some(last) => (*first..=last).map(Episode::Regular).collect(),
+                    None => vec![],
+                },
+            };
+            for episode in episodes {
+                if !wanted.iter().any(|w| w.episode == episode) {
+                    wanted.push(Wanted { episode, version: *version });
+                }
+            }
+        }
+        wanted.sort_by(|a, b| a.episode.cmp(&b.episode));
+        wanted
+    }
+}
+
+impl FromStr for EpisodeSpec {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<EpisodeSpec, String> {
+        let mut items = vec![];
+        let mut default_version = None;
+        for part in text.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (part, version) = match VERSION_REGEX.captures(part) {
+                Some(caps) => (caps.get(1).unwrap().as_str(), Some(caps[2].parse::<u8>().map_err(|_| format!("{} is not a release version", &caps[2]))?)),
+                None => (part, None),
+            };
+            if part.is_empty() {
+                default_version = version;
+                continue;
+            }
+            items.push((parse_item(part)?, version));
+        }
+        if items.is_empty() {
+            return Err("No episodes given, try e.g. 1-5,8 or latest".to_string());
+        }
+
+        for (_, version) in items.iter_mut() {
+            *version = version.or(default_version);
+        }
+        Ok(EpisodeSpec { items })
+    }
+}
+
+fn parse_item(text: &str) -> Result<Item, String> {
+    match text.to_lowercase().as_str() {
+        "all" => return Ok(Item::All),
+        "latest" => return Ok(Item::Latest),
+        _ => {},
+    }
+    if let Some(dash) = text.find('-').filter(|_| !SPECIAL_REGEX.is_match(text)) {
+        let number = |n: &str| n.trim().parse::<u32>().map_err(|_| format!("{} is not a valid episode range", text));
+        let first = number(&text[..dash])?;
+        let last = match text[dash + 1..].trim() {
+            "" => None,
+            last => Some(number(last)?),
+        };
+        if last.map_or(false, |last| last < first) {
+            return Err(format!("{} ends before it starts", text));
+        }
+        return Ok(Item::Range(first, last));
+    }
+    text.parse().map(Item::Single)
+}
+
+impl fmt::Display for EpisodeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.items.iter()
+            .map(|(item, version)| {
+                let item = match item {
+                    Item::All => "all".to_string(),
+                    Item::Latest => "latest".to_string(),
+                    Item::Single(episode) => episode.to_string(),
+                    Item::Range(first, Some(last)) if first == last => first.to_string(),
+                    Item::Range(first, Some(last)) => format!("{}-{}", first, last),
+                    Item::Range(first, None) => format!("{}-", first),
+                };
+                match version {
+                    Some(v) => format!("{}v{}", item, v),
+                    None => item,
+                }
+            })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn special(kind: &str, number: Option<u32>) -> Episode {
+        Episode::Special { kind: kind.to_string(), number }
+    }
+
+    fn episodes(wanted: &[Wanted]) -> Vec<String> {
+        wanted.iter().map(|w| match w.version {
+            Some(v) => format!("{}v{}", w.episode, v),
+            None => w.episode.to_string(),
+        }).collect()
+    }
+
+    #[test]
+    fn parses_episodes() {
+        let cases = vec![
+            ("12", Ok(Episode::Regular(12))),
+            (" 7 ", Ok(Episode::Regular(7))),
+            ("12.5", Ok(Episode::Half(12))),
+            ("OVA", Ok(special("OVA", None))),
+            ("ova 2", Ok(special("OVA", Some(2)))),
+            ("SP1", Ok(special("SP", Some(1)))),
+            ("Special 3", Ok(special("SP", Some(3)))),
+            ("12.3", Err(())),
+            ("pilot", Err(())),
+            ("99999999999", Err(())),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(text.parse::<Episode>().map_err(|_| ()), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn airing_order() {
+        let mut episodes = vec![special("OVA", Some(1)), Episode::Regular(13), Episode::Half(12), Episode::Regular(12), special("OVA", None)];
+        episodes.sort();
+        assert_eq!(episodes, vec![Episode::Regular(12), Episode::Half(12), Episode::Regular(13), special("OVA", None), special("OVA", Some(1))]);
+    }
+
+    #[test]
+    fn selects_specs() {
+        let available: Vec<Episode> = (1..=12).map(Episode::Regular).chain(vec![Episode::Half(6), special("OVA", None)]).collect();
+        // spec, needs the listing, selected
+        let cases: Vec<(&str, bool, Vec<&str>)> = vec![
+            ("1-5,8,10-", true, vec!["1", "2", "3", "4", "5", "8", "10", "11", "12"]),
+            ("latest", true, vec!["12"]),
+            ("12.5", true, vec!["12.5"]),
+            ("6.5", true, vec!["6.5"]),
+            ("OVA", true, vec!["OVA"]),
+            ("SP1", true, vec!["SP1"]),
+            ("3-4", false, vec!["3", "4"]),
+            ("3v2,5", false, vec!["3v2", "5"]),
+            ("v2,3,4v3", false, vec!["3v2", "4v3"]),
+            ("8,2,8", false, vec!["2", "8"]),
+            ("all", true, vec!["1", "2", "3", "4", "5", "6", "6.5", "7", "8", "9", "10", "11", "12", "OVA"]),
+        ];
+        for (text, needs_listing, selected) in cases {
+            let spec: EpisodeSpec = text.parse().unwrap();
+            assert_eq!(spec.needs_listing(), needs_listing, "{}", text);
+            assert_eq!(episodes(&spec.select(&available)), selected, "{}", text);
+        }
+        let open: EpisodeSpec = "10-".parse().unwrap();
+        assert!(open.select(&[]).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        for text in &["", ",", "v2", "5-3", "a-b", "1-x", "soon"] {
+            assert!(text.parse::<EpisodeSpec>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn displays_specs() {
+        for text in &["1-5,8,10-", "latest", "12.5", "OVA", "SP1", "3v2"] {
+            assert_eq!(text.parse::<EpisodeSpec>().unwrap().to_string(), *text);
+        }
+        assert_eq!(EpisodeSpec::range(4, 2).to_string(), "4");
+    }
+}
@@ -2,73 +2,163 @@ extern crate reqwest;
 extern crate serde;
 
 use reqwest::Error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::result::Result;
+use std::time::Duration;
+
+use crate::anime_episode::{Episode, Wanted};
+use crate::anime_proxy::Proxy;
+use crate::anime_release::{self, Release};
 
 const API_URL: &str = "https://api.nibl.co.uk/nibl";
+const REQUEST_TIMEOUT: u64 = 30; // seconds a search API call may take
 
-#[derive(Clone)]
+/// A pack offered by an XDCC bot, as found by the search API
+#[derive(Clone, Serialize)]
 pub struct DCCPackage {
+    #[serde(rename = "pack")]
     pub number: i32,
     pub bot: String,
     pub filename: String,
+    #[serde(rename = "size")]
     pub sizekbits: i64,
 }
 
-pub fn find_package(query: &String, episode: &Option<u16>) -> Result<DCCPackage, String> {
-    let packages = match search_packages(query, episode) {
+/// Every package the search API returns for `query` (and `episode`), best match first.
+/// Packages offered by a bot the API doesn't know are left out
+pub async fn search(query: &String, episode: &Option<u32>, proxy: &Option<Proxy>) -> Result<Vec<DCCPackage>, String> {
+    let client = match http_client(proxy) {
+        Ok(c) => c,
+        Err(e) => return Err(format!("Could not set up the search client: {}", e)),
+    };
+    let packages = match search_packages(&client, query, episode).await {
         Ok(p) => p,
         Err(e) => return Err(format!("Error while fetching results: {}", e)),
     };
+    if packages.is_empty() {
+        return Ok(vec![]);
+    }
 
-    let first_package = match packages.first() {
-        Some(p) => p,
-        None => return Err("Could not find any result for this query.".to_string()),
+    let bot_list = match get_bot_list(&client).await {
+        Ok(b) => b,
+        Err(e) => return Err(format!("Error while fetching the bot list: {}", e)),
     };
+    Ok(packages.into_iter()
+        .filter_map(|package| {
+            let bot = bot_list.iter().find(|bot| bot.id == package.bot_id)?;
+            Some(DCCPackage {
+                bot: bot.name.to_string(),
+                number: package.number,
+                filename: package.name,
+                sizekbits: package.sizekbits,
+            })
+        })
+        .collect())
+}
 
-    let bot_name = match find_bot_name(&first_package.bot_id) {
-        Some(b) => b,
-        None => return Err("Results found, but unknown bot.".to_string()),
-    };
+/// Resolves `query` (and `episode`) to the single package we'd download, the best match of [`search`]
+pub async fn find_package(query: &String, episode: &Option<u32>, proxy: &Option<Proxy>) -> Result<DCCPackage, String> {
+    match search(query, episode, proxy).await?.into_iter().next() {
+        Some(p) => Ok(p),
+        None => Err("Could not find any result for this query.".to_string()),
+    }
+}
+
+/// The packages among `packages` that hold `wanted`, best first. Releases named after the episode come first
+/// and newer versions (`05v2`) beat older ones unless a version was asked for, then the search order decides.
+/// Names the episode can't be read from only count for regular episodes outside of a season,
+/// which the API already filtered on
+pub fn matching(packages: Vec<DCCPackage>, wanted: &Wanted, season: Option<u32>) -> Vec<DCCPackage> {
+    let releases: Vec<Release> = packages.iter().map(|package| anime_release::parse(&package.filename)).collect();
+    let offset = season.and_then(|season| season_offset(&releases, season));
+
+    let mut ranked: Vec<(bool, u8, DCCPackage)> = packages.into_iter().zip(releases)
+        .filter_map(|(package, release)| {
+            let version = release.version.unwrap_or(1);
+            if wanted.version.map_or(false, |v| v != version) {
+                return None;
+            }
+            let episode = match season {
+                Some(season) => episode_in_season(&release, season, offset),
+                None => release.episode,
+            };
+            match episode {
+                Some(episode) if episode == wanted.episode => Some((true, version, package)),
+                None if season.is_none() && wanted.episode.search_number().is_some() => Some((false, version, package)),
+                _ => None,
+            }
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+    ranked.into_iter().map(|(_, _, package)| package).collect()
+}
+
+/// Episodes found in `packages` in airing order, with a season only the ones of that season numbered within it
+pub fn episodes(packages: &[DCCPackage], season: Option<u32>) -> Vec<Episode> {
+    let releases: Vec<Release> = packages.iter().map(|package| anime_release::parse(&package.filename)).collect();
+    let offset = season.and_then(|season| season_offset(&releases, season));
+    let mut episodes: Vec<Episode> = releases.iter()
+        .filter_map(|release| match season {
+            Some(season) => episode_in_season(release, season, offset),
+            None => release.episode.clone(),
+        })
+        .collect();
+    episodes.sort();
+    episodes.dedup();
+    episodes
+}
+
+/// Where `release` falls in `season`, if it does. Releases without a season belong to the first one,
+/// unless they use absolute numbering past the `offset` episodes of the earlier seasons
+fn episode_in_season(release: &Release, season: u32, offset: Option<u32>) -> Option<Episode> {
+    match (release.season, &release.episode) {
+        (Some(s), episode) if s == season => episode.clone(),
+        (Some(_), _) => None,
+        (None, Some(Episode::Regular(n))) if season > 1 => offset.filter(|o| n > o).map(|o| Episode::Regular(n - o)),
+        (None, Some(Episode::Half(n))) if season > 1 => offset.filter(|o| n > o).map(|o| Episode::Half(n - o)),
+        (None, _) if season > 1 => None,
+        (None, episode) => episode.clone(),
+    }
+}
+
+/// Episodes aired before `season`, known when every earlier season shows up with its number in the release names
+fn season_offset(releases: &[Release], season: u32) -> Option<u32> {
+    (1..season)
+        .map(|s| releases.iter()
+            .filter(|release| release.season == Some(s))
+            .filter_map(|release| release.episode.as_ref().and_then(Episode::search_number))
+            .max())
+        .sum()
+}
 
-    Ok(DCCPackage {
-        bot: bot_name.to_string(),
-        number: first_package.number,
-        filename: first_package.name.clone(),
-        sizekbits: first_package.sizekbits,
-    })
+fn http_client(proxy: &Option<Proxy>) -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(REQUEST_TIMEOUT));
+    if let Some(p) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(&p.url)?);
+    }
+    builder.build()
 }
 
-fn search_packages(query: &String, episode: &Option<u16>) -> Result<Vec<Package>, Error> {
+async fn search_packages(client: &reqwest::Client, query: &String, episode: &Option<u32>) -> Result<Vec<Package>, String> {
     let mut search_url = format!("{}/search?query={}", API_URL, query);
     if episode.is_some() {
         search_url += &format!("&episodeNumber={}", episode.unwrap());
     }
-    let mut response = reqwest::get(&search_url)?;
-    let search_result: SearchResult = response.json()?;
+    let response = client.get(&search_url).send().await.map_err(|e| e.to_string())?;
+    let search_result: SearchResult = response.json().await.map_err(|e| e.to_string())?;
     if search_result.status != "OK" {
-        panic!("Could not search package: {}", search_result.message);
+        return Err(format!("Could not search package: {}", search_result.message));
     }
     Ok(search_result.content)
 }
 
-fn find_bot_name(id: &i64) -> Option<String> {
-    let bot_list = get_bot_list();
-    let bot = bot_list.iter().find(|bot| &bot.id == id);
-    match bot {
-        Some(b) => Some(b.name.to_string()),
-        None => None,
-    }
-}
-
-fn get_bot_list() -> Vec<Bot> {
-    let mut response =
-        reqwest::get(&format!("{}/bots", API_URL)).expect("Could not fetch bot list");
-    let bot_list: BotList = response.json().expect("Could not parse bot list.");
+async fn get_bot_list(client: &reqwest::Client) -> Result<Vec<Bot>, String> {
+    let response = client.get(&format!("{}/bots", API_URL)).send().await.map_err(|e| e.to_string())?;
+    let bot_list: BotList = response.json().await.map_err(|e| e.to_string())?;
     if bot_list.status != "OK" {
-        panic!("Could not fetch bot list: {}", bot_list.message);
+        return Err(format!("Could not fetch bot list: {}", bot_list.message));
     }
-    bot_list.content
+    Ok(bot_list.content)
 }
 
 #[derive(Deserialize)]
@@ -100,3 +190,46 @@ struct Package {
     _size: String,
     sizekbits: i64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packages(filenames: &[&str]) -> Vec<DCCPackage> {
+        filenames.iter().enumerate()
+            .map(|(number, filename)| DCCPackage { number: number as i32, bot: "Bot".to_string(), filename: filename.to_string(), sizekbits: 0 })
+            .collect()
+    }
+
+    fn matched(packages: Vec<DCCPackage>, wanted: &Wanted, season: Option<u32>) -> Vec<String> {
+        matching(packages, wanted, season).into_iter().map(|package| package.filename).collect()
+    }
+
+    #[test]
+    fn prefers_newer_versions() {
+        let found = packages(&["[A] Show - 05 [720p].mkv", "[B] Show - 05v3 [720p].mkv", "[C] Show - 05v2 [720p].mkv", "[D] Show - 06 [720p].mkv"]);
+        let wanted = Wanted { episode: Episode::Regular(5), version: None };
+        assert_eq!(matched(found.clone(), &wanted, None), vec!["[B] Show - 05v3 [720p].mkv", "[C] Show - 05v2 [720p].mkv", "[A] Show - 05 [720p].mkv"]);
+
+        let wanted = Wanted { episode: Episode::Regular(5), version: Some(2) };
+        assert_eq!(matched(found.clone(), &wanted, None), vec!["[C] Show - 05v2 [720p].mkv"]);
+        let wanted = Wanted { episode: Episode::Regular(5), version: Some(1) };
+        assert_eq!(matched(found, &wanted, None), vec!["[A] Show - 05 [720p].mkv"]);
+    }
+
+    #[test]
+    fn named_episodes_before_unreadable_names() {
+        let found = packages(&["Show Batch.mkv", "[A] Show - 05 [720p].mkv"]);
+        let wanted = Wanted { episode: Episode::Regular(5), version: None };
+        assert_eq!(matched(found.clone(), &wanted, None), vec!["[A] Show - 05 [720p].mkv", "Show Batch.mkv"]);
+        assert_eq!(matched(found, &wanted, Some(1)), vec!["[A] Show - 05 [720p].mkv"]);
+    }
+
+    #[test]
+    fn absolute_numbering_across_seasons() {
+        let found = packages(&["[A] Show S1 - 12.mkv", "[A] Show - 12.5.mkv", "[A] Show - 13.mkv", "[A] Show - 13.5.mkv", "[B] Show S2 - 02.mkv"]);
+        assert_eq!(episodes(&found, Some(2)), vec![Episode::Regular(1), Episode::Half(1), Episode::Regular(2)]);
+        assert_eq!(episodes(&found, None), vec![Episode::Regular(2), Episode::Regular(12), Episode::Half(12), Episode::Regular(13), Episode::Half(13)]);
+        assert_eq!(episodes(&found, Some(3)), vec![]);
+    }
+}
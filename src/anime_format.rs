@@ -0,0 +1,116 @@
+//! Small text helpers shared by modules that have nothing else in common
+
+use std::path::{Path, PathBuf};
+
+/// Undoes `%XX` escapes, leaving malformed ones as they are
+pub fn percent_decode(encoded: &str) -> Vec<u8> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = encoded.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    decoded
+}
+
+/// Percent-encodes everything in `path` but unreserved characters and slashes, as the trash spec asks for.
+/// Keeps names that aren't UTF-8 or have tabs and line breaks in them, in files with one path per line
+pub fn encode_path(path: &Path) -> String {
+    path_bytes(path).into_iter()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Undoes `encode_path`
+pub fn decode_path(encoded: &str) -> PathBuf {
+    path_from_bytes(percent_decode(encoded))
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().as_bytes().to_vec()
+}
+
+/// A path from raw bytes, such as decoded ones. Unix paths can be any bytes, elsewhere they have to be UTF-8
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// `1:02:03` or `12:34`
+pub fn format_position(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        assert_eq!(format_position(0.0), "0:00");
+        assert_eq!(format_position(59.9), "0:59");
+        assert_eq!(format_position(754.0), "12:34");
+        assert_eq!(format_position(3723.0), "1:02:03");
+        assert_eq!(format_position(36000.0), "10:00:00");
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("p%40ss%3Aw%25rd"), b"p@ss:w%rd");
+        assert_eq!(percent_decode("%E3%81%82"), "あ".as_bytes());
+        assert_eq!(percent_decode("plain"), b"plain");
+    }
+
+    #[test]
+    fn keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%zz%4"), b"%zz%4");
+    }
+
+    #[test]
+    fn paths_round_trip() {
+        for path in &["/anime/Show S2/[Group] Show - 01 (1080p).mkv", "/anime/100%/ünïcode.mkv", "relative/name.mkv", "/a\tb\nc.mkv"] {
+            assert_eq!(decode_path(&encode_path(Path::new(path))), Path::new(path));
+        }
+        assert_eq!(encode_path(Path::new("/a b/c%d.mkv")), "/a%20b/c%25d.mkv");
+        assert_eq!(encode_path(Path::new("/a\nb")), "/a%0Ab");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_utf8() {
+        let path = path_from_bytes(b"/anime/caf\xe9.mkv".to_vec());
+        assert_eq!(encode_path(&path), "/anime/caf%E9.mkv");
+        assert_eq!(decode_path("/anime/caf%E9.mkv"), path);
+    }
+}
@@ -0,0 +1,291 @@
+extern crate chrono;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, TimeZone};
+
+use crate::{anime_config, anime_format};
+
+const HISTORY_FILE: &str = "history";
+const WATCH_FILE: &str = "watch";
+const ENCODED_PATHS: &str = "#encoded"; // first line of watch files whose paths are percent-encoded, older ones have them as they are
+const RESUME_MIN: f64 = 10.0; // seconds, starting over is fine below this
+const WATCHED_FRACTION: f64 = 0.9; // stopping this far in counts as watched, the rest is usually credits
+
+/// Something that happened to an episode, most recent last in the history file
+pub struct HistoryEntry {
+    pub time: i64,
+    pub action: String,
+    pub path: PathBuf,
+}
+
+impl HistoryEntry {
+    /// Local date and time, e.g. `2020-05-17 21:04`
+    pub fn formatted_time(&self) -> String {
+        format_time(self.time)
+    }
+}
+
+/// Local date and time of a unix timestamp, e.g. `2020-05-17 21:04`
+pub fn format_time(time: i64) -> String {
+    match Local.timestamp_opt(time, 0).single() {
+        Some(local) => local.format("%Y-%m-%d %H:%M").to_string(),
+        None => time.to_string(),
+    }
+}
+
+/// Where playback of a file got to
+#[derive(Clone)]
+pub struct WatchState {
+    pub position: f64,
+    pub duration: f64,
+    pub completed: bool,
+    pub time: i64,
+}
+
+impl WatchState {
+    /// Seconds to pick up from, none when the file was finished or barely started
+    pub fn resume_position(&self) -> Option<f64> {
+        if self.completed || self.position < RESUME_MIN {
+            None
+        } else {
+            Some(self.position)
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    anime_config::data_dir().map(|dir| dir.join(HISTORY_FILE))
+}
+
+fn watch_path() -> Option<PathBuf> {
+    anime_config::data_dir().map(|dir| dir.join(WATCH_FILE))
+}
+
+/// `path` from the root, so entries still mean something when anime-cli runs from elsewhere.
+/// `.` and `..` are worked out on the text, so `Show/../Show/01.mkv` and `Show/01.mkv` make the same key
+pub fn absolute_path(path: &Path) -> PathBuf {
+    let path = match env::current_dir() {
+        Ok(dir) if !path.is_absolute() => dir.join(path),
+        _ => path.to_path_buf(),
+    };
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => match normal.components().next_back() {
+                Some(Component::Normal(_)) => { normal.pop(); },
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}, // nothing above the root
+                _ => normal.push(".."),
+            },
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Appends `action` for `path` to the history
+pub fn record(action: &str, path: &Path) -> std::io::Result<()> {
+    let history = history_path()
+        .ok_or(std::io::Error::new(std::io::ErrorKind::NotFound, "Could not find a data directory"))?;
+    if let Some(dir) = history.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(history)?;
+    writeln!(file, "{}\t{}\t{}", now(), action, absolute_path(path).display())?;
+    file.flush()
+}
+
+pub fn record_download(path: &Path) -> std::io::Result<()> {
+    record("downloaded", path)
+}
+
+/// Every entry, oldest first
+pub fn load() -> Vec<HistoryEntry> {
+    let content = history_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    content.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            Some(HistoryEntry {
+                time: fields.next()?.parse().ok()?,
+                action: fields.next()?.to_string(),
+                path: PathBuf::from(fields.next()?),
+            })
+        })
+        .collect()
+}
+
+/// Forgets what was downloaded and watched
+pub fn clear() -> std::io::Result<()> {
+    for path in vec![history_path(), watch_path()].into_iter().filter_map(|path| path) {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The last known playback state of every file that was played, by absolute path
+pub fn watch_states() -> HashMap<PathBuf, WatchState> {
+    let content = watch_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    parse_watch_states(&content)
+}
+
+fn parse_watch_states(content: &str) -> HashMap<PathBuf, WatchState> {
+    let encoded = content.lines().next() == Some(ENCODED_PATHS);
+    content.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            let state = WatchState {
+                time: fields.next()?.parse().ok()?,
+                position: fields.next()?.parse().ok()?,
+                duration: fields.next()?.parse().ok()?,
+                completed: fields.next()? == "1",
+            };
+            let path = fields.next()?;
+            Some((if encoded { anime_format::decode_path(path) } else { PathBuf::from(path) }, state))
+        })
+        .collect()
+}
+
+/// Remembers that playback of `path` got to `position` of `duration` seconds. Getting close enough to the end
+/// counts as watched, which also goes into the history. Once watched, a file stays watched when it's rewatched
+pub fn save_watch_state(path: &Path, position: f64, duration: f64, finished: bool) -> std::io::Result<()> {
+    let path = absolute_path(path);
+    let mut states = watch_states();
+    let was_completed = states.get(&path).map_or(false, |state| state.completed);
+    let completed = finished || (duration > 0.0 && position >= duration * WATCHED_FRACTION);
+    states.insert(path.clone(), WatchState { position, duration, completed: completed || was_completed, time: now() });
+    write_watch_states(&states)?;
+
+    if completed && !was_completed {
+        record("watched", &path)?;
+    }
+    Ok(())
+}
+
+/// Marks `paths` as watched without playing them, or forgets that they were played at all
+pub fn set_watched(paths: &[PathBuf], watched: bool) -> std::io::Result<()> {
+    let mut states = watch_states();
+    let mut newly_watched = vec![];
+    for path in paths.iter().map(|path| absolute_path(path)) {
+        if watched {
+            let (duration, was_completed) = states.get(&path).map_or((0.0, false), |state| (state.duration, state.completed));
+            if !was_completed {
+                newly_watched.push(path.clone());
+            }
+            states.insert(path, WatchState { position: duration, duration, completed: true, time: now() });
+        } else {
+            states.remove(&path);
+        }
+    }
+    write_watch_states(&states)?;
+
+    // In the history like episodes played to the end
+    for path in newly_watched {
+        record("watched", &path)?;
+    }
+    Ok(())
+}
+
+/// Keeps what's known about files under `from` after they were moved to `to`
+pub fn move_watch_states(from: &Path, to: &Path) -> std::io::Result<()> {
+    let (from, to) = (absolute_path(from), absolute_path(to));
+    let mut states = watch_states();
+    let moved: Vec<PathBuf> = states.keys().filter(|path| path.starts_with(&from)).cloned().collect();
+    if moved.is_empty() {
+        return Ok(());
+    }
+    for path in moved {
+        if let Some(state) = states.remove(&path) {
+            let rest = path.strip_prefix(&from).unwrap_or(Path::new(""));
+            states.insert(if rest.as_os_str().is_empty() { to.clone() } else { to.join(rest) }, state);
+        }
+    }
+    write_watch_states(&states)
+}
+
+/// Written next to the old file and renamed over it, so a crash or a second anime-cli halfway through
+/// can't leave a cut off watch file behind
+fn write_watch_states(states: &HashMap<PathBuf, WatchState>) -> std::io::Result<()> {
+    let watch = watch_path()
+        .ok_or(std::io::Error::new(std::io::ErrorKind::NotFound, "Could not find a data directory"))?;
+    if let Some(dir) = watch.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = watch.with_file_name(format!("{}.{}.tmp", WATCH_FILE, std::process::id()));
+    let written = write_states_to(&temp, states).and_then(|_| fs::rename(&temp, &watch));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_states_to(path: &Path, states: &HashMap<PathBuf, WatchState>) -> std::io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    writeln!(file, "{}", ENCODED_PATHS)?;
+    for (path, state) in states {
+        writeln!(file, "{}\t{:.1}\t{:.1}\t{}\t{}", state.time, state.position, state.duration,
+                 if state.completed { 1 } else { 0 }, anime_format::encode_path(path))?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn watch_states_round_trip() {
+        let dir = env::temp_dir().join(format!("anime-cli-history-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut states = HashMap::new();
+        states.insert(PathBuf::from("/anime/Show/Show - 01.mkv"), WatchState { time: 1589742240, position: 1420.0, duration: 1420.5, completed: true });
+        states.insert(PathBuf::from("/anime/Show/Show\t02.mkv"), WatchState { time: 1589742300, position: 61.25, duration: 1420.0, completed: false });
+        states.insert(PathBuf::from("/anime/Show/Show\n03.mkv"), WatchState { time: 1589742400, position: 12.0, duration: 1420.0, completed: false });
+        let not_utf8 = anime_format::path_from_bytes(b"/anime/Caf\xe9/04.mkv".to_vec());
+        states.insert(not_utf8.clone(), WatchState { time: 1589742500, position: 30.0, duration: 1420.0, completed: false });
+        let path = dir.join("watch");
+        write_states_to(&path, &states).unwrap();
+
+        let read = parse_watch_states(&fs::read_to_string(&path).unwrap());
+        assert_eq!(read.len(), 4);
+        let first = &read[Path::new("/anime/Show/Show - 01.mkv")];
+        assert!(first.completed && first.time == 1589742240 && (first.duration - 1420.5).abs() < 0.01);
+        let second = &read[Path::new("/anime/Show/Show\t02.mkv")];
+        assert!(!second.completed && (second.position - 61.25).abs() < 0.1);
+        assert_eq!(read[Path::new("/anime/Show/Show\n03.mkv")].time, 1589742400);
+        assert_eq!(read[&not_utf8].time, 1589742500);
+
+        // Written before paths were encoded
+        let old = parse_watch_states("1589742240\t1420.0\t1420.5\t1\t/anime/Show/Show - 01 100%20.mkv\n");
+        assert!(old[Path::new("/anime/Show/Show - 01 100%20.mkv")].completed);
+        assert!(parse_watch_states("garbage\n1\t2\n").is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn absolute_paths_are_normal() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(absolute_path(Path::new("/anime/./Show/../Show/01.mkv")), Path::new("/anime/Show/01.mkv"));
+        assert_eq!(absolute_path(Path::new("/../anime/01.mkv")), Path::new("/anime/01.mkv"));
+        assert_eq!(absolute_path(Path::new("./Show/../Show/01.mkv")), cwd.join("Show/01.mkv"));
+        assert_eq!(absolute_path(Path::new("../list.m3u8")), cwd.parent().unwrap().join("list.m3u8"));
+        assert_eq!(absolute_path(Path::new("Show/")), cwd.join("Show"));
+    }
+}
@@ -0,0 +1,185 @@
+extern crate tokio;
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time;
+
+// 512 bytes per RFC 1459 plus the 8191 bytes IRCv3 allows for message tags
+pub const MAX_LINE_LENGTH: usize = 8703;
+const READ_CHUNK: usize = 4096;
+
+pub enum ReadError {
+    Eof,
+    Timeout(Duration),
+    TooLong,
+    Io(Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Eof => write!(f, "IRC server closed the connection"),
+            ReadError::Timeout(after) => write!(f, "No message from the IRC server for {} seconds", after.as_secs()),
+            ReadError::TooLong => write!(f, "IRC server sent a line longer than {} bytes", MAX_LINE_LENGTH),
+            ReadError::Io(e) => write!(f, "Error reading from the IRC server: {}", e),
+        }
+    }
+}
+
+impl From<ReadError> for Error {
+    fn from(e: ReadError) -> Error {
+        let kind = match &e {
+            ReadError::Eof => ErrorKind::ConnectionAborted,
+            ReadError::Timeout(_) => ErrorKind::TimedOut,
+            ReadError::TooLong => ErrorKind::InvalidData,
+            ReadError::Io(e) => e.kind(),
+        };
+        Error::new(kind, e.to_string())
+    }
+}
+
+/// Splits what the server sends into lines, whatever size the reads come in
+pub struct IRCLineReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    discarding: bool, // skipping the rest of a line that was too long
+}
+
+impl<R: AsyncRead + Unpin> IRCLineReader<R> {
+    pub fn new(reader: R) -> IRCLineReader<R> {
+        IRCLineReader { reader, buffer: Vec::with_capacity(READ_CHUNK), discarding: false }
+    }
+
+    /// Next line without its CRLF (a bare LF is accepted too, some servers send those).
+    /// Bytes only leave the buffer once a whole line is returned, so dropping this future
+    /// halfway, e.g. when another `select!` branch wins, loses nothing
+    pub async fn read_line(&mut self, timeout: Duration) -> Result<String, ReadError> {
+        let mut scanned = 0;
+        loop {
+            if let Some(offset) = self.buffer[scanned..].iter().position(|&b| b == b'\n') {
+                let end = scanned + offset;
+                let mut line: Vec<u8> = self.buffer.drain(..=end).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                if self.discarding {
+                    self.discarding = false;
+                    return Err(ReadError::TooLong);
+                }
+                return Ok(decode(line));
+            }
+            if self.buffer.len() > MAX_LINE_LENGTH {
+                // Keep reading until the end of it, but don't hold on to the bytes
+                self.buffer.clear();
+                self.discarding = true;
+            }
+            scanned = self.buffer.len();
+
+            let mut chunk = [0; READ_CHUNK];
+            let count = match time::timeout(timeout, self.reader.read(&mut chunk[..])).await {
+                Ok(Ok(0)) => return Err(ReadError::Eof),
+                Ok(Ok(count)) => count,
+                Ok(Err(e)) => return Err(ReadError::Io(e)),
+                Err(_) => return Err(ReadError::Timeout(timeout)),
+            };
+            self.buffer.extend_from_slice(&chunk[..count]);
+        }
+    }
+}
+
+/// IRC has no fixed encoding, most of it is UTF-8 but older clients and bots still send Latin-1
+fn decode(line: Vec<u8>) -> String {
+    match String::from_utf8(line) {
+        Ok(line) => line,
+        Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// Hands out the given chunks one read at a time, then reports end of file
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl AsyncRead for Chunks {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            if let Some(mut chunk) = self.0.pop_front() {
+                let count = chunk.len().min(buf.remaining());
+                buf.put_slice(&chunk[..count]);
+                if count < chunk.len() {
+                    self.0.push_front(chunk.split_off(count));
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn from_chunks(chunks: &[&[u8]]) -> IRCLineReader<Chunks> {
+        IRCLineReader::new(Chunks(chunks.iter().map(|chunk| chunk.to_vec()).collect()))
+    }
+
+    async fn line(reader: &mut IRCLineReader<Chunks>) -> String {
+        match reader.read_line(WAIT).await {
+            Ok(line) => line,
+            Err(e) => panic!("expected a line, got: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn lines_split_across_reads() {
+        let mut reader = from_chunks(&[b"PI", b"NG :irc.example", b".org\r", b"\n:bot PRIVMSG me :hi\r\n:bot NOT", b"ICE me :x\r\n"]);
+        assert_eq!(line(&mut reader).await, "PING :irc.example.org");
+        assert_eq!(line(&mut reader).await, ":bot PRIVMSG me :hi");
+        assert_eq!(line(&mut reader).await, ":bot NOTICE me :x");
+        assert!(matches!(reader.read_line(WAIT).await, Err(ReadError::Eof)));
+    }
+
+    #[tokio::test]
+    async fn crlf_and_bare_lf() {
+        let mut reader = from_chunks(&[b"first\r\nsecond\nthird\r\r\n\n"]);
+        assert_eq!(line(&mut reader).await, "first");
+        assert_eq!(line(&mut reader).await, "second");
+        assert_eq!(line(&mut reader).await, "third\r");
+        assert_eq!(line(&mut reader).await, "");
+    }
+
+    #[tokio::test]
+    async fn non_utf8_is_read_as_latin1() {
+        let mut reader = from_chunks(&[b"caf\xc3\xa9\r\n", b"caf\xe9\r\n", b"\xff\xfe\r\n"]);
+        assert_eq!(line(&mut reader).await, "café");
+        assert_eq!(line(&mut reader).await, "café");
+        assert_eq!(line(&mut reader).await, "\u{ff}\u{fe}");
+    }
+
+    #[tokio::test]
+    async fn over_long_line_is_skipped() {
+        let long = vec![b'a'; MAX_LINE_LENGTH * 2];
+        let (start, rest) = long.split_at(READ_CHUNK + 10);
+        let mut reader = from_chunks(&[b"before\r\n", start, rest, b"\r\nafter\r\n"]);
+        assert_eq!(line(&mut reader).await, "before");
+        assert!(matches!(reader.read_line(WAIT).await, Err(ReadError::TooLong)));
+        assert_eq!(line(&mut reader).await, "after");
+
+        let mut reader = from_chunks(&[&vec![b'b'; MAX_LINE_LENGTH][..], b"\r\n"]);
+        assert_eq!(line(&mut reader).await.len(), MAX_LINE_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn times_out_without_data() {
+        let (_server, client) = tokio::io::duplex(64);
+        let mut reader = IRCLineReader::new(client);
+        let timeout = Duration::from_millis(20);
+        assert!(matches!(reader.read_line(timeout).await, Err(ReadError::Timeout(after)) if after == timeout));
+    }
+}
@@ -0,0 +1,300 @@
+use std::cmp::Reverse;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::anime_episode::Episode;
+use crate::anime_history;
+use crate::anime_probe;
+use crate::anime_release::{self, Release};
+
+pub const DEFAULT_TEMPLATE: &str = "{title}/Season {season:02}/{title} - S{season:02}E{episode:02} [{resolution}][{crc}].{ext}";
+pub const DEFAULT_MOVIE_TEMPLATE: &str = "{title}/{title} [{resolution}][{crc}].{ext}";
+
+const SUBTITLE_EXTENSIONS: &'static [&'static str] = &["ass", "idx", "srt", "ssa", "sub", "sup", "vtt"];
+const PLACEHOLDERS: &'static [&'static str] = &["title", "season", "episode", "version", "resolution", "codec", "group", "crc", "ext", "original"];
+
+lazy_static! {
+    static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\{(\w+)(?::0(\d))?\}").unwrap();
+    static ref EMPTY_TAG_REGEX: Regex = Regex::new(r"\s*[\[(]\s*[\])]").unwrap();
+    static ref SPACE_BEFORE_EXT_REGEX: Regex = Regex::new(r"\s+(\.[A-Za-z0-9]+)$").unwrap();
+    static ref SPACES_REGEX: Regex = Regex::new(r"\s{2,}").unwrap();
+    static ref SEASON_FOLDER_REGEX: Regex = Regex::new(r"(?i)^Season\s*(\d+)$").unwrap();
+}
+
+/// Where episodes and movies go, relative to the anime folder
+pub struct Templates {
+    pub episode: String,
+    pub movie: String,
+}
+
+impl Templates {
+    /// The given templates, or the defaults for the ones that aren't
+    pub fn new(episode: Option<&str>, movie: Option<&str>) -> Templates {
+        Templates {
+            episode: episode.unwrap_or(DEFAULT_TEMPLATE).to_string(),
+            movie: movie.unwrap_or(DEFAULT_MOVIE_TEMPLATE).to_string(),
+        }
+    }
+}
+
+/// A rename the organiser wants to make
+pub struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Checks that `template` only uses placeholders the organiser knows
+pub fn validate_template(template: &str) -> Result<(), String> {
+    for caps in PLACEHOLDER_REGEX.captures_iter(template) {
+        if !PLACEHOLDERS.contains(&&caps[1]) {
+            return Err(format!("Unknown placeholder {{{}}}, known ones are {}", &caps[1],
+                               PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")));
+        }
+    }
+    let escapes = Path::new(template).components()
+        .any(|component| matches!(component, Component::Prefix(_) | Component::RootDir | Component::ParentDir));
+    if escapes {
+        return Err("Templates have to stay inside the anime folder".to_string());
+    }
+    Ok(())
+}
+
+/// Fills in `template` for `file`, `{season:02}` pads to two digits. Values that are missing come out empty
+/// and the empty brackets around them are dropped, e.g. `[{resolution}]` for a release without one
+pub fn render(template: &str, release: &Release, file: &Path) -> Result<PathBuf, String> {
+    validate_template(template)?;
+    let ext = file.extension().and_then(OsStr::to_str).unwrap_or("");
+    let original = file.file_stem().and_then(OsStr::to_str).unwrap_or("");
+
+    let mut path = PathBuf::new();
+    for part in template.split('/') {
+        let filled = PLACEHOLDER_REGEX.replace_all(part, |caps: &Captures| {
+            let width = caps.get(2).and_then(|w| w.as_str().parse().ok()).unwrap_or(0);
+            let number = |n: Option<u32>| n.map(|n| format!("{:0width$}", n, width = width)).unwrap_or_default();
+            clean_name(&match &caps[1] {
+                "title" => release.title.clone(),
+                "season" => number(release.season),
+                "episode" => match &release.episode {
+                    Some(Episode::Regular(n)) => number(Some(*n)),
+                    Some(Episode::Half(n)) => format!("{}.5", number(Some(*n))),
+                    Some(special) => special.to_string(),
+                    None => String::new(),
+                },
+                "version" => number(release.version.map(u32::from)),
+                "resolution" => release.resolution.map(|r| format!("{}p", r)).unwrap_or_default(),
+                "codec" => release.codec.clone().unwrap_or_default(),
+                "group" => release.group.clone().unwrap_or_default(),
+                "crc" => release.crc.map(|crc| format!("{:08X}", crc)).unwrap_or_default(),
+                "ext" => ext.to_string(),
+                _ => original.to_string(),
+            })
+        });
+        let tidy = EMPTY_TAG_REGEX.replace_all(&filled, "");
+        let tidy = SPACE_BEFORE_EXT_REGEX.replace(&tidy, "$1");
+        let tidy = SPACES_REGEX.replace_all(&tidy, " ");
+        let tidy = tidy.trim_matches(|c: char| c.is_whitespace() || c == '-' || c == '_');
+        if tidy.is_empty() || tidy == "." || tidy == ".." {
+            return Err(format!("{} leaves an empty folder or file name for {}", template, file.display()));
+        }
+        path.push(tidy);
+    }
+    Ok(path)
+}
+
+/// File and folder names can't hold these on every system, they become spaces
+pub fn clean_name(name: &str) -> String {
+    let cleaned: String = name.chars()
+        .map(|c| if "<>:\"/\\|?*".contains(c) || c.is_control() { ' ' } else { c })
+        .collect();
+    cleaned.trim().trim_end_matches('.').to_string()
+}
+
+/// The moves that put `file` (and any subtitles next to it) where `templates` say under `anime_dir`.
+/// The season and title fall back to the `Series/Season NN` folders the file is in, then to season 1,
+/// and the resolution and codec to what the file's headers say
+pub fn plan(file: &Path, anime_dir: &Path, templates: &Templates) -> Result<Vec<Move>, String> {
+    let filename = file.file_name().and_then(OsStr::to_str).ok_or(format!("{} is not a file", file.display()))?;
+    let mut release = anime_release::parse(filename);
+    if release.resolution.is_none() || release.codec.is_none() {
+        if let Ok(info) = anime_probe::probe(file) {
+            release.resolution = release.resolution.or(info.resolution());
+            release.codec = release.codec.or(info.video().map(|video| video.codec.clone()));
+        }
+    }
+
+    let parent = file.parent().unwrap_or(Path::new(""));
+    let folder_name = |dir: &Path| dir.file_name().and_then(OsStr::to_str).map(str::to_string);
+    let season_folder = folder_season(parent);
+    let series_folder = if season_folder.is_some() { parent.parent().and_then(folder_name) } else { folder_name(parent) };
+    if release.title.is_empty() {
+        release.title = series_folder.unwrap_or_default();
+    }
+
+    let template = match release.episode {
+        Some(_) => {
+            release.season = release.season.or(season_folder).or(Some(1));
+            &templates.episode
+        },
+        // Something in a season folder is an episode even when its name doesn't say which, so it stays put
+        None if season_folder.is_some() => return Ok(vec![Move { from: file.to_path_buf(), to: file.to_path_buf() }]),
+        None => &templates.movie,
+    };
+    let target = anime_dir.join(render(template, &release, file)?);
+
+    let mut moves = vec![];
+    if let (Some(stem), Some(target_stem)) = (file.file_stem().and_then(OsStr::to_str), target.file_stem().and_then(OsStr::to_str)) {
+        for subtitle in subtitles(parent, stem) {
+            let rest = &subtitle.file_name().and_then(OsStr::to_str).unwrap_or("")[stem.len()..];
+            moves.push(Move { to: target.with_file_name(format!("{}{}", target_stem, rest)), from: subtitle });
+        }
+    }
+    moves.insert(0, Move { from: file.to_path_buf(), to: target });
+    Ok(moves)
+}
+
+/// The season a `Season NN` folder holds, however it's capitalised
+pub fn folder_season(dir: &Path) -> Option<u32> {
+    dir.file_name()
+        .and_then(OsStr::to_str)
+        .and_then(|name| SEASON_FOLDER_REGEX.captures(name.trim()))
+        .and_then(|caps| caps[1].parse().ok())
+}
+
+/// Subtitle files named after the video with `stem`, e.g. `<stem>.ass` or `<stem>.en.srt`
+fn subtitles(dir: &Path, stem: &str) -> Vec<PathBuf> {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut found: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+            let ext = path.extension().and_then(OsStr::to_str).unwrap_or("").to_lowercase();
+            name.starts_with(&format!("{}.", stem)) && SUBTITLE_EXTENSIONS.contains(&ext.as_str())
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// Makes the moves, creating folders as needed. Moves that change nothing are skipped,
+/// and nothing that's already there gets overwritten. When one fails the ones made before it are undone,
+/// so a video never ends up separated from its subtitles. Watch states move along with the files
+pub fn apply(moves: &[Move]) -> std::io::Result<()> {
+    let mut done: Vec<&Move> = vec![];
+    let mut created: Vec<PathBuf> = vec![];
+    let result = moves.iter()
+        .filter(|m| m.from != m.to)
+        .try_for_each(|m| {
+            if m.to.exists() {
+                return Err(Error::new(ErrorKind::AlreadyExists, format!("{} already exists", m.to.display())));
+            }
+            if let Some(dir) = m.to.parent() {
+                created.extend(dir.ancestors().take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists()).map(Path::to_path_buf));
+                fs::create_dir_all(dir)?;
+            }
+            fs::rename(&m.from, &m.to)?;
+            let _ = anime_history::move_watch_states(&m.from, &m.to);
+            done.push(m);
+            Ok(())
+        });
+
+    if result.is_err() {
+        for m in done.iter().rev() {
+            match fs::rename(&m.to, &m.from) {
+                Ok(_) => { let _ = anime_history::move_watch_states(&m.to, &m.from); },
+                Err(e) => eprintln!("Could not move {} back to {}: {}", m.to.display(), m.from.display(), e),
+            }
+        }
+        created.sort_by_key(|dir| Reverse(dir.components().count()));
+        for dir in created {
+            let _ = fs::remove_dir(dir); // fails when something else was put there meanwhile
+        }
+    }
+    result
+}
+
+/// Removes `dir` and the folders above it up to `anime_dir` for as long as they're empty
+pub fn remove_empty_dirs(dir: &Path, anime_dir: &Path) {
+    let mut current = dir.to_path_buf();
+    while current != anime_dir && current.starts_with(anime_dir) {
+        if fs::remove_dir(&current).is_err() { // fails when it isn't empty
+            break;
+        }
+        if !current.pop() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn templates_stay_inside() {
+        assert!(validate_template(DEFAULT_TEMPLATE).is_ok());
+        assert!(validate_template(DEFAULT_MOVIE_TEMPLATE).is_ok());
+        assert!(validate_template("{title}/./{original}.{ext}").is_ok());
+        assert!(validate_template("{title}/{nope}.{ext}").is_err());
+        assert!(validate_template("/{title}/{original}.{ext}").is_err());
+        assert!(validate_template("{title}/../{original}.{ext}").is_err());
+        assert!(validate_template("../{original}.{ext}").is_err());
+        assert!(validate_template("{title}/..").is_err());
+    }
+
+    #[test]
+    fn renders_default_templates() {
+        let file = Path::new("[Group] Show S2 - 05v2 [1080p][ABCD1234].mkv");
+        let release = anime_release::parse(file.to_str().unwrap());
+        assert_eq!(render(DEFAULT_TEMPLATE, &release, file).unwrap(), Path::new("Show/Season 02/Show - S02E05 [1080p][ABCD1234].mkv"));
+
+        let file = Path::new("Movie: The Film.mp4");
+        let release = anime_release::parse(file.to_str().unwrap());
+        assert_eq!(render(DEFAULT_MOVIE_TEMPLATE, &release, file).unwrap(), Path::new("Movie The Film/Movie The Film.mp4"));
+        assert!(render("{group}/{original}.{ext}", &release, file).is_err());
+    }
+
+    #[test]
+    fn season_folders() {
+        assert_eq!(folder_season(Path::new("Show/Season 02")), Some(2));
+        assert_eq!(folder_season(Path::new("Show/season 3")), Some(3));
+        assert_eq!(folder_season(Path::new("SEASON10")), Some(10));
+        assert_eq!(folder_season(Path::new("Show/Season 2 Extras")), None);
+        assert_eq!(folder_season(Path::new("Show/Seasonal")), None);
+        assert_eq!(folder_season(Path::new("Show")), None);
+    }
+
+    #[test]
+    fn failed_moves_are_undone() {
+        let dir = std::env::temp_dir().join(format!("anime-cli-organize-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("taken")).unwrap();
+        for name in &["ep.mkv", "ep.ass", "ep.srt", "taken/ep.srt"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let moves = vec![
+            Move { from: dir.join("ep.mkv"), to: dir.join("Show/Season 01/ep.mkv") },
+            Move { from: dir.join("ep.ass"), to: dir.join("Show/Season 01/ep.ass") },
+            Move { from: dir.join("ep.srt"), to: dir.join("taken/ep.srt") },
+        ];
+        assert_eq!(apply(&moves).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        for name in &["ep.mkv", "ep.ass", "ep.srt"] {
+            assert_eq!(fs::read_to_string(dir.join(name)).unwrap(), *name);
+        }
+        assert!(!dir.join("Show").exists());
+
+        apply(&moves[..2]).unwrap();
+        assert!(dir.join("Show/Season 01/ep.mkv").exists() && dir.join("Show/Season 01/ep.ass").exists());
+        assert!(!dir.join("ep.mkv").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
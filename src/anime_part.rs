@@ -0,0 +1,265 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+const PART_SUFFIX: &str = ".part";
+const META_SUFFIX: &str = ".part.meta";
+
+lazy_static! {
+    // Release groups tag files with the CRC32 of their content, e.g. "[HorribleSubs] Show - 01 [720p][1A2B3C4D].mkv"
+    static ref CRC_REGEX: Regex = Regex::new(r#"[\[(]([0-9A-Fa-f]{8})[\])]"#).unwrap();
+    static ref CRC_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for i in 0..256 {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+            table[i] = crc;
+        }
+        table
+    };
+}
+
+/// What we know about an unfinished transfer, stored next to the `.part` file
+pub struct PartMeta {
+    pub expected_size: u64,
+    pub bot: String,
+    pub pack: String,
+}
+
+/// `<name>.part`, where bytes are written while a transfer is in progress
+pub fn part_path(file_path: &Path) -> PathBuf {
+    append_to_path(file_path, PART_SUFFIX)
+}
+
+/// `<name>.part.meta`, the sidecar describing the transfer
+pub fn meta_path(file_path: &Path) -> PathBuf {
+    append_to_path(file_path, META_SUFFIX)
+}
+
+fn append_to_path(file_path: &Path, suffix: &str) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Size of the partial download for `file_path`, 0 if there is none
+pub fn partial_len(file_path: &Path) -> u64 {
+    match fs::metadata(part_path(file_path)) {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Throws away a partial download and its sidecar
+pub fn discard(file_path: &Path) {
+    let _ = fs::remove_file(part_path(file_path));
+    let _ = fs::remove_file(meta_path(file_path));
+}
+
+/// Byte offset a transfer of `file_size` bytes can resume from, if there's a usable partial download.
+/// Leftovers that belong to another release or are larger than the file are thrown away
+pub fn find_resumable(file_path: &Path, file_size: u64) -> Option<u64> {
+    if let Some(meta) = read_meta(file_path) {
+        if meta.expected_size != file_size {
+            discard(file_path);
+            return None;
+        }
+    }
+
+    let len = partial_len(file_path);
+    if len > file_size {
+        discard(file_path);
+        None
+    } else if len > 0 && len < file_size {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Cuts the partial download back to `len` bytes, when a bot resumes earlier than we asked
+pub fn truncate(file_path: &Path, len: u64) -> std::io::Result<()> {
+    fs::OpenOptions::new().write(true).open(part_path(file_path))?.set_len(len)
+}
+
+pub fn write_meta(file_path: &Path, meta: &PartMeta) -> std::io::Result<()> {
+    let mut file = fs::File::create(meta_path(file_path))?;
+    write!(file, "size={}\nbot={}\npack={}\n", meta.expected_size, meta.bot, meta.pack)?;
+    file.flush()
+}
+
+pub fn read_meta(file_path: &Path) -> Option<PartMeta> {
+    let file = fs::File::open(meta_path(file_path)).ok()?;
+    let mut expected_size = None;
+    let mut bot = String::new();
+    let mut pack = String::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.ok()?;
+        let mut kv = line.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("size"), Some(v)) => expected_size = v.parse::<u64>().ok(),
+            (Some("bot"), Some(v)) => bot = v.to_string(),
+            (Some("pack"), Some(v)) => pack = v.to_string(),
+            _ => {}
+        }
+    }
+
+    Some(PartMeta { expected_size: expected_size?, bot, pack })
+}
+
+/// CRC32 advertised in a release name, if the group put one there. A bracketed tag opening the name is
+/// the group, even when it happens to be eight hex digits
+pub fn crc_from_filename(filename: &str) -> Option<u32> {
+    CRC_REGEX.captures_iter(filename)
+        .filter(|captures| captures.get(0).map_or(false, |tag| tag.start() > 0))
+        .last()
+        .and_then(|captures| u32::from_str_radix(&captures[1], 16).ok())
+}
+
+pub fn crc32_of_file(path: &Path) -> std::io::Result<u32> {
+    crc32_of_file_with_progress(path, |_| true)
+}
+
+/// Like [`crc32_of_file`], telling `progress` how many bytes were read so far. Returning false from it
+/// stops reading with an `Interrupted` error
+pub fn crc32_of_file_with_progress(path: &Path, mut progress: impl FnMut(u64) -> bool) -> std::io::Result<u32> {
+    let mut file = fs::File::open(path)?;
+    let mut buffer = [0; 64 * 1024];
+    let mut crc = 0xFFFF_FFFFu32;
+    let mut read = 0;
+    loop {
+        let count = file.read(&mut buffer[..])?;
+        if count == 0 { break; }
+        for byte in &buffer[..count] {
+            crc = CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+        }
+        read += count as u64;
+        if !progress(read) {
+            return Err(Error::new(ErrorKind::Interrupted, "CRC check cancelled"));
+        }
+    }
+
+    Ok(!crc)
+}
+
+/// Result of checking a file against the CRC32 in its name
+pub enum Verification {
+    Match(u32),
+    Mismatch { expected: u32, actual: u32 },
+    NoChecksum,
+}
+
+/// Checks a finished file against the CRC32 its release group put in the name
+pub fn verify(path: &Path) -> std::io::Result<Verification> {
+    verify_with_progress(path, |_| true)
+}
+
+/// [`verify`] reporting progress the way [`crc32_of_file_with_progress`] does
+pub fn verify_with_progress(path: &Path, progress: impl FnMut(u64) -> bool) -> std::io::Result<Verification> {
+    let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let expected = match crc_from_filename(filename) {
+        Some(crc) => crc,
+        None => return Ok(Verification::NoChecksum),
+    };
+
+    let actual = crc32_of_file_with_progress(path, progress)?;
+    Ok(if actual == expected { Verification::Match(actual) } else { Verification::Mismatch { expected, actual } })
+}
+
+/// Checks the `.part` file is complete (and matches its CRC when the name has one),
+/// then renames it into place in a single step so nothing ever sees a half-written episode
+pub fn finalize(file_path: &Path, expected_size: u64) -> std::io::Result<()> {
+    let part = part_path(file_path);
+    let size = fs::metadata(&part)?.len();
+    if size != expected_size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, format!("Incomplete download, got {} of {} bytes", size, expected_size)));
+    }
+
+    let filename = file_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    if let Some(expected_crc) = crc_from_filename(filename) {
+        let crc = crc32_of_file(&part)?;
+        if crc != expected_crc {
+            return Err(Error::new(ErrorKind::InvalidData, format!("CRC mismatch, expected {:08X} but got {:08X}", expected_crc, crc)));
+        }
+    }
+
+    fs::rename(&part, file_path)?;
+    let _ = fs::remove_file(meta_path(file_path));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `name` in a folder of its own, for the test to remove when it's done
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("anime-cli-part-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn crc_in_brackets_or_parentheses() {
+        assert_eq!(crc_from_filename("[Group] Show - 01 [720p][ABCD1234].mkv"), Some(0xABCD1234));
+        assert_eq!(crc_from_filename("[Group] Show - 01 (720p) (abcd1234).mkv"), Some(0xABCD1234));
+        assert_eq!(crc_from_filename("[Group] Show - 01 [DEADBEEF][0000ABCD].mkv"), Some(0x0000ABCD));
+    }
+
+    #[test]
+    fn no_crc() {
+        assert_eq!(crc_from_filename("[Group] Show - 01 [720p].mkv"), None);
+        assert_eq!(crc_from_filename("[Group] Show - 01 [ABCD123].mkv"), None);
+        assert_eq!(crc_from_filename("[Group] Show - 01 [ABCD12345].mkv"), None);
+        assert_eq!(crc_from_filename("Show - 01 ABCD1234.mkv"), None);
+    }
+
+    #[test]
+    fn group_tag_is_not_a_crc() {
+        assert_eq!(crc_from_filename("[CAFEBABE] Show - 01 [720p].mkv"), None);
+        assert_eq!(crc_from_filename("[CAFEBABE] Show - 01 [12345678].mkv"), Some(0x12345678));
+    }
+
+    #[test]
+    fn crc32_of_known_content() {
+        let path = temp_file("crc.bin");
+        fs::write(&path, b"123456789").unwrap();
+        assert_eq!(crc32_of_file(&path).unwrap(), 0xCBF43926);
+        let mut seen = vec![];
+        assert_eq!(crc32_of_file_with_progress(&path, |read| { seen.push(read); true }).unwrap(), 0xCBF43926);
+        assert_eq!(seen, vec![9]);
+        let stopped = crc32_of_file_with_progress(&path, |_| false);
+        assert_eq!(stopped.unwrap_err().kind(), ErrorKind::Interrupted);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn meta_round_trip() {
+        let path = temp_file("Show - 01.mkv");
+        write_meta(&path, &PartMeta { expected_size: 1234, bot: "Bot|1080p".to_string(), pack: "#42".to_string() }).unwrap();
+        let meta = read_meta(&path).unwrap();
+        assert_eq!((meta.expected_size, meta.bot.as_str(), meta.pack.as_str()), (1234, "Bot|1080p", "#42"));
+
+        fs::write(meta_path(&path), "bot=Bot\npack=#1\n").unwrap();
+        assert!(read_meta(&path).is_none(), "a meta file without a size is useless");
+        discard(&path);
+        assert!(read_meta(&path).is_none());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn resumes_only_matching_partial_downloads() {
+        let path = temp_file("Show - 02.mkv");
+        fs::write(part_path(&path), vec![0; 10]).unwrap();
+        write_meta(&path, &PartMeta { expected_size: 100, bot: String::new(), pack: String::new() }).unwrap();
+        assert_eq!(find_resumable(&path, 100), Some(10));
+        assert_eq!(find_resumable(&path, 200), None);
+        assert!(!part_path(&path).exists(), "a partial download of another size is thrown away");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,540 @@
+#[cfg(feature = "mpv")]
+extern crate mpv;
+extern crate serde_json;
+
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::{anime_config, anime_probe};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1); // how often VLC and MPlayer are asked where they are
+const END_MARGIN: f64 = 3.0; // seconds from the end a polled player has to get to for the file to count as played through
+const PLAYERS: &str = "builtin, mpv, vlc, mplayer, system or a command with {file} or {files}";
+
+static MPV_SOCKETS: AtomicUsize = AtomicUsize::new(0); // each mpv gets its own socket, two can play at once
+
+/// A file to play and where to start it, in seconds
+pub struct Item {
+    pub path: PathBuf,
+    pub start: Option<f64>,
+}
+
+/// What a player tells about playback, `index` being the position of the file in what it was given
+pub enum PlayerEvent {
+    Position { index: usize, position: f64, duration: f64 },
+    /// Played to the end
+    Finished(usize),
+}
+
+/// Something that plays a list of files in order
+pub trait Player {
+    /// Plays `items` and returns once the player is closed or got through them all.
+    /// Players that can tell where they are report it to `events`
+    fn play(&mut self, items: &[Item], events: &mut dyn FnMut(PlayerEvent)) -> std::io::Result<()>;
+
+    /// Whether it can start on a .part file that's still being downloaded
+    fn streams(&self) -> bool {
+        false
+    }
+}
+
+/// The player set with `anime-cli config set player`: the linked libmpv when built with it, the system's default
+/// player otherwise
+pub fn from_config(config: &anime_config::Config) -> Result<Box<dyn Player>, String> {
+    parse(config.get("player").unwrap_or(if cfg!(feature = "mpv") { "builtin" } else { "system" }))
+}
+
+/// `builtin` for libmpv, `system` for whatever opens videos, `mpv`, `vlc` or `mplayer` (or a path to one of them,
+/// quoted or not), or a command where `{file}`/`{files}` and `{start}` are filled in
+pub fn parse(value: &str) -> Result<Box<dyn Player>, String> {
+    let value = value.trim();
+    if value.contains("{file}") || value.contains("{files}") {
+        return Ok(Box::new(CustomCommand::new(value)?));
+    }
+    let program = match split_words(value) {
+        Ok(words) if words.len() == 1 => words[0].clone(),
+        _ => value.to_string(), // a path with spaces in it
+    };
+    match known_player(&program) {
+        #[cfg(feature = "mpv")]
+        Some("builtin") => Ok(Box::new(Libmpv)),
+        #[cfg(not(feature = "mpv"))]
+        Some("builtin") => Err("anime-cli was built without mpv, pick another player".to_string()),
+        Some("system") => Ok(Box::new(System)),
+        Some("mpv") => Ok(Box::new(Mpv { program })),
+        Some("vlc") => Ok(Box::new(vlc(&program))),
+        Some("mplayer") => Ok(Box::new(mplayer(&program))),
+        _ => Err(format!("Unknown player {}, use {}", value, PLAYERS)),
+    }
+}
+
+/// Which player `program` is: `builtin` and `system` as they are, the others by file name,
+/// so `/usr/bin/mpv` and `C:\Program Files\mpv\mpv.exe` are both mpv
+fn known_player(program: &str) -> Option<&'static str> {
+    if let Some(name) = ["builtin", "system"].iter().find(|name| **name == program) {
+        return Some(name);
+    }
+    let file = program.rsplit(&['/', '\\'][..]).next()?;
+    let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem).to_lowercase();
+    ["mpv", "vlc", "mplayer"].iter().find(|name| **name == stem).copied()
+}
+
+/// mpv linked into anime-cli
+#[cfg(feature = "mpv")]
+pub struct Libmpv;
+
+#[cfg(feature = "mpv")]
+impl Player for Libmpv {
+    fn play(&mut self, items: &[Item], events: &mut dyn FnMut(PlayerEvent)) -> std::io::Result<()> {
+        let mpv_error = |e: mpv::Error| Error::new(ErrorKind::Other, format!("mpv: {:?}", e));
+        let mut mpv_builder = mpv::MpvHandlerBuilder::new().map_err(mpv_error)?;
+        mpv_builder.set_option("osc", true).map_err(mpv_error)?;
+        mpv_builder.set_option("input-default-bindings", true).map_err(mpv_error)?;
+        mpv_builder.set_option("input-vo-keyboard", true).map_err(mpv_error)?;
+        let mut mpv = mpv_builder.build().map_err(mpv_error)?;
+        let _ = mpv.observe_property::<f64>("time-pos", 0);
+        let _ = mpv.observe_property::<f64>("duration", 0);
+
+        let mut index = 0;
+        let mut duration = 0.0;
+        mpv.command(&["loadfile", &items[index].path.to_string_lossy()]).map_err(mpv_error)?;
+        loop {
+            while let Some(event) = mpv.wait_event(1.0) {
+                match event {
+                    mpv::Event::Shutdown => return Ok(()),
+                    mpv::Event::FileLoaded => {
+                        duration = 0.0;
+                        if let Some(start) = items[index].start {
+                            let _ = mpv.command(&["seek", &start.to_string(), "absolute"]);
+                        }
+                    },
+                    mpv::Event::PropertyChange { name: "time-pos", change: mpv::Format::Double(position), .. } =>
+                        events(PlayerEvent::Position { index, position, duration }),
+                    mpv::Event::PropertyChange { name: "duration", change: mpv::Format::Double(d), .. } => duration = d,
+                    mpv::Event::EndFile(Ok(mpv::EndFileReason::MPV_END_FILE_REASON_EOF)) => {
+                        events(PlayerEvent::Finished(index));
+                        index += 1;
+                        if index >= items.len() {
+                            return Ok(());
+                        }
+                        mpv.command(&["loadfile", &items[index].path.to_string_lossy()]).map_err(mpv_error)?;
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    fn streams(&self) -> bool {
+        true
+    }
+}
+
+/// The mpv program, followed through its JSON IPC socket
+pub struct Mpv {
+    program: String,
+}
+
+impl Player for Mpv {
+    fn play(&mut self, items: &[Item], events: &mut dyn FnMut(PlayerEvent)) -> std::io::Result<()> {
+        let count = MPV_SOCKETS.fetch_add(1, Ordering::Relaxed);
+        let socket = std::env::temp_dir().join(format!("anime-cli-mpv-{}-{}.sock", std::process::id(), count));
+        let mut command = Command::new(&self.program);
+        command.arg("--force-window=yes").arg("--no-terminal").arg(format!("--input-ipc-server={}", socket.display()));
+        for item in items {
+            match item.start {
+                Some(start) => command.args(&["--{".to_string(), format!("--start={}", start)]).arg(&item.path).arg("--}"),
+                None => command.arg(&item.path),
+            };
+        }
+        let mut child = spawn(command.stdin(Stdio::null()).stdout(Stdio::null()), &self.program)?;
+        #[cfg(unix)]
+        follow_mpv(&socket, &mut child, events)?;
+        #[cfg(not(unix))]
+        let _ = (&socket, events); // no socket to follow it through
+        child.wait()?;
+        let _ = std::fs::remove_file(&socket);
+        Ok(())
+    }
+
+    fn streams(&self) -> bool {
+        true
+    }
+}
+
+/// Reads mpv's events until it quits
+#[cfg(unix)]
+fn follow_mpv(socket: &Path, child: &mut Child, events: &mut dyn FnMut(PlayerEvent)) -> std::io::Result<()> {
+    use std::os::unix::net::UnixStream;
+
+    let mut waited = Duration::from_secs(0);
+    let stream = loop { // mpv takes a moment to open the socket
+        match UnixStream::connect(socket) {
+            Ok(stream) => break stream,
+            Err(_) if child.try_wait()?.is_none() && waited < Duration::from_secs(10) => {
+                thread::sleep(Duration::from_millis(100));
+                waited += Duration::from_millis(100);
+            },
+            Err(_) => return Ok(()), // closed already, or too old to have the socket
+        }
+    };
+    let mut writer = stream.try_clone()?;
+    for (id, property) in ["playlist-pos", "time-pos", "duration"].iter().enumerate() {
+        writeln!(writer, r#"{{"command":["observe_property",{},"{}"]}}"#, id + 1, property)?;
+    }
+
+    let (mut index, mut duration) = (0, 0.0);
+    for line in BufReader::new(stream).lines() {
+        let message: serde_json::Value = match line.map(|line| serde_json::from_str(&line)) {
+            Ok(Ok(message)) => message,
+            Ok(Err(_)) => continue,
+            Err(_) => break,
+        };
+        match (message["event"].as_str(), message["name"].as_str()) {
+            (Some("property-change"), Some("playlist-pos")) => index = message["data"].as_u64().map_or(index, |pos| pos as usize),
+            (Some("property-change"), Some("duration")) => duration = message["data"].as_f64().unwrap_or(duration),
+            (Some("property-change"), Some("time-pos")) => if let Some(position) = message["data"].as_f64() {
+                events(PlayerEvent::Position { index, position, duration });
+            },
+            (Some("end-file"), _) if message["reason"] == "eof" => // entries are numbered from 1 in the order they were given
+                events(PlayerEvent::Finished(message["playlist_entry_id"].as_u64().map_or(index, |id| id.saturating_sub(1) as usize))),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// What a polled player said
+#[derive(Debug, PartialEq)]
+enum Answer {
+    /// Moved on to the next file
+    Started,
+    Time(f64),
+    Length(f64),
+    /// A bare number, answering whichever question was asked last
+    Number(f64),
+}
+
+/// Questions for a polled player, with what a bare number means as the answer
+type Questions = &'static [(&'static str, fn(f64) -> Answer)];
+
+const VLC_QUESTIONS: Questions = &[("get_time\n", Answer::Time), ("get_length\n", Answer::Length)];
+const MPLAYER_QUESTIONS: Questions = &[
+    ("pausing_keep_force get_time_pos\n", Answer::Time),
+    ("pausing_keep_force get_time_length\n", Answer::Length),
+];
+
+/// A player taking commands on stdin and answering on stdout, like VLC's rc interface and MPlayer's slave mode.
+/// It's asked where it is and how long the file is every second, one question at a time since VLC answers
+/// with bare numbers, and told to seek when a file with a start position begins
+pub struct Polled {
+    program: String,
+    args: Vec<&'static str>,
+    questions: Questions,
+    seek: fn(f64) -> String,
+    parse: fn(&str) -> Option<Answer>,
+}
+
+fn vlc(program: &str) -> Polled {
+    let mut args = vec!["--play-and-exit", "--extraintf", "rc"];
+    if cfg!(unix) {
+        args.push("--rc-fake-tty"); // or rc won't read from a pipe
+    }
+    Polled {
+        program: program.to_string(),
+        args,
+        questions: VLC_QUESTIONS,
+        seek: |start| format!("seek {}\n", start.round()),
+        parse: |line| {
+            let line = line.trim_start_matches(&['>', ' '][..]);
+            if line.contains("new input:") {
+                Some(Answer::Started)
+            } else {
+                line.trim().parse().ok().map(Answer::Number)
+            }
+        },
+    }
+}
+
+fn mplayer(program: &str) -> Polled {
+    Polled {
+        program: program.to_string(),
+        args: vec!["-slave", "-quiet"],
+        questions: MPLAYER_QUESTIONS,
+        seek: |start| format!("seek {} 2\n", start),
+        parse: |line| {
+            let value = |prefix: &str| line.strip_prefix(prefix).and_then(|value| value.trim().parse().ok());
+            if line.starts_with("Playing ") {
+                Some(Answer::Started)
+            } else if let Some(time) = value("ANS_TIME_POSITION=") {
+                Some(Answer::Time(time))
+            } else {
+                value("ANS_LENGTH=").map(Answer::Length)
+            }
+        },
+    }
+}
+
+impl Player for Polled {
+    fn play(&mut self, items: &[Item], events: &mut dyn FnMut(PlayerEvent)) -> std::io::Result<()> {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).args(items.iter().map(|item| &item.path));
+        let mut child = spawn(command.stdin(Stdio::piped()).stdout(Stdio::piped()), &self.program)?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().unwrap()));
+        let stdout = child.stdout.take().unwrap();
+
+        // The next question waits for the answer to the last one, or a second when there is none (no file playing)
+        let (answered, answers) = mpsc::channel();
+        let asking = Arc::new(AtomicUsize::new(0));
+        let (poller, questions, asked) = (stdin.clone(), self.questions, asking.clone());
+        thread::spawn(move || { // stops when the player is gone
+            for (question, (text, _)) in questions.iter().enumerate().cycle() {
+                asked.store(question, Ordering::SeqCst);
+                if poller.lock().unwrap().write_all(text.as_bytes()).is_err() {
+                    break;
+                }
+                if let Err(mpsc::RecvTimeoutError::Disconnected) = answers.recv_timeout(POLL_INTERVAL) {
+                    break;
+                }
+                thread::sleep(POLL_INTERVAL / questions.len() as u32);
+            }
+        });
+
+        let mut current: Option<usize> = None;
+        let (mut time, mut length) = (0.0, None);
+        let played_through = |time: f64, length: Option<f64>| length.map_or(false, |length| time >= length - END_MARGIN);
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let answer = match (self.parse)(&line) {
+                Some(Answer::Number(number)) => Some(self.questions[asking.load(Ordering::SeqCst)].1(number)),
+                answer => answer,
+            };
+            if let Some(Answer::Time(_)) | Some(Answer::Length(_)) = answer {
+                let _ = answered.send(());
+            }
+            match answer {
+                Some(Answer::Started) => {
+                    if let Some(index) = current.filter(|_| played_through(time, length)) {
+                        events(PlayerEvent::Finished(index));
+                    }
+                    let index = current.map_or(0, |index| index + 1);
+                    current = Some(index);
+                    time = 0.0;
+                    // Until the player tells
+                    length = items.get(index).and_then(|item| anime_probe::probe(&item.path).ok()).and_then(|info| info.duration);
+                    if let Some(start) = items.get(index).and_then(|item| item.start) {
+                        let _ = stdin.lock().unwrap().write_all((self.seek)(start).as_bytes());
+                    }
+                },
+                Some(Answer::Time(position)) => if let Some(index) = current {
+                    time = position;
+                    events(PlayerEvent::Position { index, position, duration: length.unwrap_or(0.0) });
+                },
+                Some(Answer::Length(duration)) if duration > 0.0 => length = Some(duration),
+                _ => {},
+            }
+        }
+        if let Some(index) = current.filter(|_| played_through(time, length)) {
+            events(PlayerEvent::Finished(index));
+        }
+        child.wait()?;
+        Ok(())
+    }
+}
+
+/// A command line from the config. `{file}` plays the files one at a time and can be part of a word
+/// (`--open={file}`), `{files}` plays them all at once and has to be a word of its own, `{start}` is where the
+/// (first) file starts. Quotes keep words with spaces together. It can't tell where playback got to
+pub struct CustomCommand {
+    words: Vec<String>,
+}
+
+impl CustomCommand {
+    fn new(template: &str) -> Result<Self, String> {
+        let words = split_words(template)?;
+        let mentions = |placeholder: &str| words.iter().any(|word| word.contains(placeholder));
+        if words.first().map_or(true, |program| program.contains('{')) {
+            return Err(format!("{} has to start with the player to run", template));
+        }
+        if mentions("{files}") && mentions("{file}") {
+            return Err(format!("{} has both {{file}} and {{files}}, pick one", template));
+        }
+        if words.iter().any(|word| word.contains("{files}") && word != "{files}") {
+            return Err(format!("{{files}} has to be a word of its own in {}", template));
+        }
+        if !mentions("{file}") && !mentions("{files}") {
+            return Err(format!("{} has no {{file}} or {{files}}", template));
+        }
+        Ok(CustomCommand { words })
+    }
+
+    /// The program and its arguments for playing `items`
+    fn command_line(&self, items: &[Item]) -> Vec<OsString> {
+        let start = items[0].start.unwrap_or(0.0).to_string();
+        let mut args = vec![];
+        for word in &self.words {
+            if word == "{files}" || word == "{file}" {
+                args.extend(items.iter().map(|item| item.path.as_os_str().to_os_string()));
+                continue;
+            }
+            // Paths don't have to be UTF-8, so they're put between the pieces rather than into the text
+            let word = word.replace("{start}", &start);
+            let mut pieces = word.split("{file}");
+            let mut arg = OsString::from(pieces.next().unwrap_or(""));
+            for piece in pieces {
+                arg.push(&items[0].path);
+                arg.push(piece);
+            }
+            args.push(arg);
+        }
+        args
+    }
+
+    fn run(&self, items: &[Item]) -> std::io::Result<()> {
+        let mut args = self.command_line(items);
+        let program = args.remove(0);
+        spawn(Command::new(&program).args(args).stdin(Stdio::null()).stdout(Stdio::null()), &program.to_string_lossy())?.wait()?;
+        Ok(())
+    }
+}
+
+impl Player for CustomCommand {
+    fn play(&mut self, items: &[Item], _: &mut dyn FnMut(PlayerEvent)) -> std::io::Result<()> {
+        match self.words.iter().any(|word| word == "{files}") {
+            true => self.run(items),
+            false => items.iter().try_for_each(|item| self.run(std::slice::from_ref(item))),
+        }
+    }
+}
+
+/// Splits a command line at spaces outside quotes. Single and double quotes both keep what's between them as it is,
+/// backslashes included so Windows paths can be written as they are
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None; // None between words, so "" still makes an empty word
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            },
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(open) = quote {
+        return Err(format!("{} has a {} that isn't closed", line, open));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Whatever the system opens videos with. It only gets the first file since there's no telling when it's done
+pub struct System;
+
+impl Player for System {
+    fn play(&mut self, items: &[Item], _: &mut dyn FnMut(PlayerEvent)) -> std::io::Result<()> {
+        opener::open(&items[0].path).map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
+    }
+}
+
+/// Starts a player quietly, its output would mess up the browser
+fn spawn(command: &mut Command, program: &str) -> std::io::Result<Child> {
+    command.stderr(Stdio::null()).spawn().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => Error::new(e.kind(), format!("Could not find {}, install it or pick another player with anime-cli config set player", program)),
+        _ => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_player() {
+        let known = |program| known_player(program);
+        assert_eq!(known("builtin"), Some("builtin"));
+        assert_eq!(known("system"), Some("system"));
+        assert_eq!(known("mpv"), Some("mpv"));
+        assert_eq!(known("mpv.exe"), Some("mpv"));
+        assert_eq!(known("/usr/local/bin/mplayer"), Some("mplayer"));
+        assert_eq!(known(r"C:\Program Files\VideoLAN\VLC\VLC.EXE"), Some("vlc"));
+        assert_eq!(known("/opt/mpv-build/celluloid"), None);
+        assert_eq!(known("Builtin"), None);
+
+        assert!(parse("mpv").unwrap().streams());
+        assert!(parse(" \"/opt/my players/mpv\" ").unwrap().streams());
+        assert!(parse("/opt/my players/mpv").unwrap().streams());
+        assert!(!parse("vlc").unwrap().streams());
+        assert!(!parse("mplayer.exe").unwrap().streams());
+        assert!(!parse("system").unwrap().streams());
+        assert_eq!(parse("builtin").is_ok(), cfg!(feature = "mpv"));
+        assert!(parse("totem").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn checks_commands() {
+        assert!(parse("mpv --fs {files}").is_ok());
+        assert!(parse("\"C:\\Program Files\\mpv\\mpv.exe\" --start={start} {file}").is_ok());
+        assert!(parse("mpv --input={files}").is_err());
+        assert!(parse("mpv {file} {files}").is_err());
+        assert!(parse("{file} --fs").is_err());
+        assert!(parse("'mpv {file}").is_err());
+    }
+
+    #[test]
+    fn splits_words() {
+        let split = |line| split_words(line).unwrap();
+        assert_eq!(split("  mpv   --fs {files} "), vec!["mpv", "--fs", "{files}"]);
+        assert_eq!(split(r#""C:\Program Files\mpv\mpv.exe" {file}"#), vec![r"C:\Program Files\mpv\mpv.exe", "{file}"]);
+        assert_eq!(split(r#"vlc --meta-title='Now "playing"' ''"#), vec!["vlc", r#"--meta-title=Now "playing""#, ""]);
+        assert!(split_words("mpv \"{file}").is_err());
+    }
+
+    #[test]
+    fn fills_in_commands() {
+        let items = vec![
+            Item { path: PathBuf::from("/anime/Show S2/01.mkv"), start: Some(83.5) },
+            Item { path: PathBuf::from("/anime/Show S2/02.mkv"), start: None },
+        ];
+        let command_line = |template: &str, items: &[Item]| -> Vec<String> {
+            CustomCommand::new(template).unwrap().command_line(items).iter().map(|arg| arg.to_string_lossy().to_string()).collect()
+        };
+        assert_eq!(command_line("'/opt/my players/play' --start={start} {files}", &items),
+                   vec!["/opt/my players/play", "--start=83.5", "/anime/Show S2/01.mkv", "/anime/Show S2/02.mkv"]);
+        assert_eq!(command_line("player --open={file} --from {start}", &items[1..]),
+                   vec!["player", "--open=/anime/Show S2/02.mkv", "--from", "0"]);
+        assert_eq!(command_line("player \"{file}\"", &items[..1]), vec!["player", "/anime/Show S2/01.mkv"]);
+    }
+
+    #[test]
+    fn reads_vlc() {
+        let parse = vlc("vlc").parse;
+        assert_eq!(parse("> ( new input: file:///anime/01.mkv )"), Some(Answer::Started));
+        assert_eq!(parse("status change: ( new input: file:///anime/01.mkv )"), Some(Answer::Started));
+        assert_eq!(parse("> 1420"), Some(Answer::Number(1420.0)));
+        assert_eq!(parse("83"), Some(Answer::Number(83.0)));
+        assert_eq!(parse("> "), None);
+        assert_eq!(parse("status change: ( play state: 3 ): Play"), None);
+        assert_eq!(VLC_QUESTIONS[1].1(1420.0), Answer::Length(1420.0));
+    }
+
+    #[test]
+    fn reads_mplayer() {
+        let parse = mplayer("mplayer").parse;
+        assert_eq!(parse("Playing /anime/01.mkv."), Some(Answer::Started));
+        assert_eq!(parse("ANS_TIME_POSITION=83.4"), Some(Answer::Time(83.4)));
+        assert_eq!(parse("ANS_LENGTH=1420.50"), Some(Answer::Length(1420.5)));
+        assert_eq!(parse("ANS_LENGTH=n/a"), None);
+        assert_eq!(parse("Starting playback..."), None);
+    }
+}
@@ -0,0 +1,197 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{anime_format, anime_history, anime_probe, anime_release};
+
+/// Extensions of the playlists `read` understands
+pub const EXTENSIONS: [&str; 2] = ["m3u", "m3u8"];
+
+/// Writes `files` as an M3U8 playlist at `playlist`, with paths relative to the playlist so the
+/// folder can be moved as a whole, or `absolute` ones for players elsewhere on the machine.
+/// Titles come from the release names, lengths from the headers
+pub fn write(playlist: &Path, files: &[PathBuf], absolute: bool) -> std::io::Result<()> {
+    let playlist = anime_history::absolute_path(playlist);
+    let base = playlist.parent().unwrap_or(Path::new("/")).to_path_buf();
+    if !base.as_os_str().is_empty() {
+        fs::create_dir_all(&base)?;
+    }
+
+    let mut file = fs::File::create(&playlist)?;
+    writeln!(file, "#EXTM3U")?;
+    for path in files.iter().map(|path| anime_history::absolute_path(path)) {
+        let duration = anime_probe::probe(&path).ok().and_then(|info| info.duration).map_or(-1, |duration| duration.round() as i64);
+        writeln!(file, "#EXTINF:{},{}", duration, title(&path))?;
+        match absolute {
+            true => writeln!(file, "{}", path.display())?,
+            false => writeln!(file, "{}", relative_to(&path, &base).display())?,
+        }
+    }
+    file.flush()
+}
+
+/// The files an M3U or M3U8 playlist lists, in order. Relative paths are taken from the playlist's folder,
+/// `file://` URLs are turned back into paths and other URLs are left out since only local files can be played
+pub fn read(playlist: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let playlist = anime_history::absolute_path(playlist);
+    let bytes = fs::read(&playlist)?;
+    // .m3u is supposed to be Latin-1, but most players write UTF-8 there too
+    let content = match String::from_utf8(bytes) {
+        Ok(content) => content,
+        Err(e) => e.into_bytes().iter().map(|&byte| byte as char).collect(),
+    };
+    let base = playlist.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+    Ok(content.lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match line.find("://") {
+            Some(_) => file_url_path(line),
+            None => Some(anime_history::absolute_path(&base.join(line))),
+        })
+        .collect())
+}
+
+/// The local path of a `file://` URL: `file:///anime/a%20b.mkv`, `file://localhost/anime/a.mkv`,
+/// or `file:///C:/anime/a.mkv` on Windows. Other hosts and schemes have none
+fn file_url_path(url: &str) -> Option<PathBuf> {
+    const SCHEME: &str = "file://";
+    if !url.get(..SCHEME.len())?.eq_ignore_ascii_case(SCHEME) {
+        return None;
+    }
+    let rest = &url[SCHEME.len()..];
+    let (host, path) = rest.split_at(rest.find('/')?);
+    if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+        return None;
+    }
+    let mut bytes = anime_format::percent_decode(path);
+    if cfg!(windows) && is_drive_path(&bytes) {
+        bytes.remove(0);
+    }
+    Some(anime_format::path_from_bytes(bytes))
+}
+
+/// `/C:/...`, how a URL writes a Windows drive path
+fn is_drive_path(path: &[u8]) -> bool {
+    matches!(path, [b'/', letter, b':', ..] if letter.is_ascii_alphabetic())
+}
+
+/// Whether `path` looks like a playlist `read` can open
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension().and_then(OsStr::to_str).map_or(false, |ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// `Show - 5` for an episode, the file name without extension for anything else
+pub fn title(path: &Path) -> String {
+    let filename = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+    let release = anime_release::parse(filename);
+    match release.episode {
+        Some(episode) if !release.title.is_empty() => format!("{} - {}", release.title, episode),
+        _ => path.file_stem().and_then(OsStr::to_str).unwrap_or(filename).to_string(),
+    }
+}
+
+/// `path` as seen from `base`, both absolute. Paths on another drive stay absolute
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let (path_parts, base_parts): (Vec<Component>, Vec<Component>) = (path.components().collect(), base.components().collect());
+    if path_parts.first() != base_parts.first() { // another drive on Windows
+        return path.to_path_buf();
+    }
+    let common = path_parts.iter().zip(&base_parts).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base_parts.len() {
+        relative.push("..");
+    }
+    for part in &path_parts[common..] {
+        relative.push(part.as_os_str());
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("anime-cli-playlist-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_paths_and_file_urls() {
+        let dir = temp_dir("read");
+        let playlist = dir.join("show.m3u8");
+        fs::write(&playlist, "\u{feff}#EXTM3U\r\n#EXTINF:1420,Show - 1\r\n[G] Show - 01.mkv\r\n\r\n../Other/02.mkv\n\
+                              /anime/Show/03.mkv\nfile:///anime/Show%20S2/04.mkv\nFILE://localhost/anime/05.mkv\n\
+                              file://server/share/06.mkv\nhttp://example.com/07.mkv\n  # a comment\n").unwrap();
+        assert_eq!(read(&playlist).unwrap(), vec![
+            dir.join("[G] Show - 01.mkv"),
+            dir.parent().unwrap().join("Other/02.mkv"),
+            PathBuf::from("/anime/Show/03.mkv"),
+            PathBuf::from("/anime/Show S2/04.mkv"),
+            PathBuf::from("/anime/05.mkv"),
+        ]);
+        assert!(read(&dir.join("missing.m3u")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn latin1_playlists() {
+        let dir = temp_dir("latin1");
+        let playlist = dir.join("latin1.m3u");
+        fs::write(&playlist, b"/anime/Caf\xe9 - 01.mkv\n").unwrap();
+        assert_eq!(read(&playlist).unwrap(), vec![PathBuf::from("/anime/Café - 01.mkv")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drive_paths_in_urls() {
+        assert!(is_drive_path(b"/C:/anime/a.mkv"));
+        assert!(is_drive_path(b"/d:"));
+        assert!(!is_drive_path(b"/anime/a.mkv"));
+        assert!(!is_drive_path(b"/1:/a.mkv"));
+        assert!(!is_drive_path(b"C:/a.mkv"));
+        assert_eq!(file_url_path("file:///anime/a.mkv"), Some(PathBuf::from("/anime/a.mkv")));
+        assert_eq!(file_url_path("file://localhost"), None);
+        assert_eq!(file_url_path("https://localhost/a.mkv"), None);
+    }
+
+    #[test]
+    fn relative_paths() {
+        let base = Path::new("/anime/Show");
+        assert_eq!(relative_to(Path::new("/anime/Show/01.mkv"), base), PathBuf::from("01.mkv"));
+        assert_eq!(relative_to(Path::new("/anime/Show/Season 2/01.mkv"), base), PathBuf::from("Season 2/01.mkv"));
+        assert_eq!(relative_to(Path::new("/anime/Other/01.mkv"), base), PathBuf::from("../Other/01.mkv"));
+        assert_eq!(relative_to(Path::new("/media/01.mkv"), base), PathBuf::from("../../media/01.mkv"));
+        assert_eq!(relative_to(Path::new("/anime/Show/01.mkv"), Path::new("/")), PathBuf::from("anime/Show/01.mkv"));
+    }
+
+    #[test]
+    fn written_playlists_read_back() {
+        let dir = temp_dir("write");
+        let files = vec![dir.join("Show/[G] Show - 01.mkv"), dir.join("Other/02.mkv")];
+        for absolute in [false, true].iter().copied() {
+            let playlist = dir.join("Show").join(format!("written-{}.m3u8", absolute));
+            write(&playlist, &files, absolute).unwrap();
+            let content = fs::read_to_string(&playlist).unwrap();
+            assert!(content.starts_with("#EXTM3U\n#EXTINF:-1,Show - 1\n"));
+            assert_eq!(content.contains(&dir.display().to_string()), absolute);
+            assert_eq!(read(&playlist).unwrap(), files);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn written_through_parent_folders() {
+        let dir = temp_dir("parent");
+        let playlist = dir.join("Show").join("..").join(".").join("list.m3u8");
+        let files = vec![dir.join("Show/./01.mkv"), dir.join("Other/../Show/02.mkv")];
+        write(&playlist, &files, false).unwrap();
+        let content = fs::read_to_string(dir.join("list.m3u8")).unwrap();
+        assert_eq!(content.lines().filter(|line| !line.starts_with('#')).collect::<Vec<_>>(), vec!["Show/01.mkv", "Show/02.mkv"]);
+        assert_eq!(read(&playlist).unwrap(), vec![dir.join("Show/01.mkv"), dir.join("Show/02.mkv")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
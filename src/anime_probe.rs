@@ -0,0 +1,744 @@
+extern crate serde;
+
+use std::fs::File;
+use std::io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::anime_format;
+
+const MAX_HEADER: u64 = 64 * 1024 * 1024; // header elements and boxes bigger than this aren't headers
+
+// Matroska element ids, see https://www.matroska.org/technical/elements.html
+const EBML: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const LANGUAGE: u32 = 0x22B59C;
+const LANGUAGE_IETF: u32 = 0x22B59D;
+const NAME: u32 = 0x536E;
+const FLAG_DEFAULT: u32 = 0x88;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const CHANNELS: u32 = 0x9F;
+const CHAPTERS: u32 = 0x1043A770;
+const EDITION_ENTRY: u32 = 0x45B9;
+const CHAPTER_ATOM: u32 = 0xB6;
+const CHAPTER_TIME_START: u32 = 0x91;
+const CHAPTER_DISPLAY: u32 = 0x80;
+const CHAP_STRING: u32 = 0x85;
+const CLUSTER: u32 = 0x1F43B675;
+
+/// What a track holds
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+    Other,
+}
+
+/// One stream in a media file
+#[derive(Clone, Debug, Serialize)]
+pub struct Track {
+    pub kind: TrackKind,
+    /// Common name like `HEVC` or `AAC`, the container's own id when it's not a known one
+    pub codec: String,
+    pub language: Option<String>,
+    pub name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub channels: Option<u32>,
+    /// Matroska's default flag, for MP4 whether the track is enabled
+    pub default: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Chapter {
+    /// Seconds from the start
+    pub start: f64,
+    pub title: Option<String>,
+}
+
+/// What the headers of a Matroska or MP4 file say about it
+#[derive(Clone, Debug, Serialize)]
+pub struct MediaInfo {
+    pub container: &'static str,
+    /// Seconds
+    pub duration: Option<f64>,
+    pub tracks: Vec<Track>,
+    pub chapters: Vec<Chapter>,
+}
+
+impl MediaInfo {
+    /// The first video track
+    pub fn video(&self) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.kind == TrackKind::Video)
+    }
+
+    pub fn tracks_of(&self, kind: TrackKind) -> Vec<&Track> {
+        self.tracks.iter().filter(|track| track.kind == kind).collect()
+    }
+
+    /// The resolution a release would be tagged with, going by width so 1920x800 is still 1080
+    pub fn resolution(&self) -> Option<u16> {
+        let video = self.video()?;
+        Some(match (video.width?, video.height?) {
+            (width, _) if width >= 3840 => 2160,
+            (width, _) if width >= 2560 => 1440,
+            (width, _) if width >= 1920 => 1080,
+            (width, _) if width >= 1280 => 720,
+            (width, _) if width >= 854 => 480,
+            (_, height) => height as u16,
+        })
+    }
+
+    /// One line, e.g. `23:40 | 1920x1080 HEVC | audio jpn, eng | subtitles eng | 5 chapters`
+    pub fn summary(&self) -> String {
+        let mut parts = vec![];
+        if let Some(duration) = self.duration {
+            parts.push(anime_format::format_position(duration));
+        }
+        if let Some(video) = self.video() {
+            parts.push(match (video.width, video.height) {
+                (Some(width), Some(height)) => format!("{}x{} {}", width, height, video.codec),
+                _ => video.codec.clone(),
+            });
+        }
+        let languages = |tracks: Vec<&Track>| tracks.iter()
+            .map(|track| track.language.clone().unwrap_or(track.codec.clone()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let audio = self.tracks_of(TrackKind::Audio);
+        if !audio.is_empty() {
+            parts.push(format!("audio {}", languages(audio)));
+        }
+        let subtitles = self.tracks_of(TrackKind::Subtitle);
+        if !subtitles.is_empty() {
+            parts.push(format!("subtitles {}", languages(subtitles)));
+        }
+        if !self.chapters.is_empty() {
+            parts.push(format!("{} chapters", self.chapters.len()));
+        }
+        parts.join(" | ")
+    }
+}
+
+/// Reads the headers of a Matroska (mkv, webm) or MP4 (mp4, m4v) file. Only the headers are read,
+/// the media data in between is skipped
+pub fn probe(path: &Path) -> std::io::Result<MediaInfo> {
+    let mut file = File::open(path)?;
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic).map_err(|_| invalid("Too short to be a media file"))?;
+    file.seek(SeekFrom::Start(0))?;
+    if magic[..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+        probe_matroska(&mut file)
+    } else if &magic[4..] == b"ftyp" || &magic[4..] == b"moov" {
+        probe_mp4(&mut file)
+    } else {
+        Err(invalid("Not a Matroska or MP4 file"))
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// An EBML id, length marker included as the ids are usually written that way
+fn read_id<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let first = read_byte(reader)?;
+    let length = first.leading_zeros() + 1;
+    if length > 4 {
+        return Err(invalid("Broken Matroska element id"));
+    }
+    let mut id = first as u32;
+    for _ in 1..length {
+        id = (id << 8) | read_byte(reader)? as u32;
+    }
+    Ok(id)
+}
+
+/// An EBML element size, none when the writer left it open (all ones)
+fn read_size<R: Read>(reader: &mut R) -> std::io::Result<Option<u64>> {
+    let first = read_byte(reader)?;
+    let length = first.leading_zeros() + 1;
+    if length > 8 {
+        return Err(invalid("Broken Matroska element size"));
+    }
+    let mask = (0xFFu32 >> length) as u64;
+    let mut size = first as u64 & mask;
+    let mut unknown = size == mask;
+    for _ in 1..length {
+        let byte = read_byte(reader)?;
+        unknown &= byte == 0xFF;
+        size = (size << 8) | byte as u64;
+    }
+    Ok(if unknown { None } else { Some(size) })
+}
+
+fn read_body(file: &mut File, size: u64) -> std::io::Result<Vec<u8>> {
+    if size > MAX_HEADER {
+        return Err(invalid("Header too large"));
+    }
+    let mut body = vec![0; size as usize];
+    file.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// The elements inside a master element, as id and body
+fn elements(data: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut cursor = Cursor::new(data);
+    let mut found = vec![];
+    while (cursor.position() as usize) < data.len() {
+        let id = match read_id(&mut cursor) {
+            Ok(id) => id,
+            Err(_) => break,
+        };
+        let size = match read_size(&mut cursor) {
+            Ok(Some(size)) => size,
+            _ => break,
+        };
+        let start = cursor.position() as usize;
+        let end = start.saturating_add(size.min(data.len() as u64) as usize).min(data.len());
+        found.push((id, &data[start..end]));
+        cursor.set_position(end as u64);
+    }
+    found
+}
+
+fn child<'a>(data: &'a [u8], id: u32) -> Option<&'a [u8]> {
+    elements(data).into_iter().find(|(found, _)| *found == id).map(|(_, body)| body)
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter().take(8).fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+fn float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64),
+        8 => Some(f64::from_be_bytes([data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7]])),
+        _ => None,
+    }
+}
+
+fn text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim_end_matches('\0').trim().to_string()
+}
+
+/// `und` and empty languages say nothing
+fn language(language: String) -> Option<String> {
+    Some(language).filter(|language| !language.is_empty() && language != "und")
+}
+
+fn probe_matroska(file: &mut File) -> std::io::Result<MediaInfo> {
+    if read_id(file)? != EBML {
+        return Err(invalid("Not a Matroska file"));
+    }
+    let header = read_size(file)?.ok_or(invalid("Broken Matroska header"))?;
+    file.seek(SeekFrom::Current(header as i64))?;
+    if read_id(file)? != SEGMENT {
+        return Err(invalid("No Matroska segment"));
+    }
+    let segment_end = read_size(file)?;
+    let segment_start = file.seek(SeekFrom::Current(0))?;
+    let segment_end = segment_end.map(|size| segment_start.saturating_add(size));
+
+    let mut info = MediaInfo { container: "Matroska", duration: None, tracks: vec![], chapters: vec![] };
+    let mut seen = vec![];
+    let mut seek_targets = vec![];
+    let mut position = segment_start;
+    // The headers come before the clusters holding the media, or the seek head says where they are
+    while segment_end.map_or(true, |end| position < end) {
+        file.seek(SeekFrom::Start(position))?;
+        let id = match read_id(file) {
+            Ok(id) => id,
+            Err(_) => break, // end of the file
+        };
+        let size = match read_size(file)? {
+            Some(size) if id != CLUSTER => size,
+            _ => break,
+        };
+        let body_start = file.seek(SeekFrom::Current(0))?;
+        match id {
+            SEEK_HEAD => seek_targets = read_seek_head(&read_body(file, size)?, segment_start),
+            INFO | TRACKS | CHAPTERS => {
+                read_matroska_element(id, &read_body(file, size)?, &mut info);
+                seen.push(id);
+            },
+            _ => {},
+        }
+        position = body_start.checked_add(size).ok_or_else(|| invalid("Broken Matroska element"))?;
+    }
+
+    for (id, target) in seek_targets {
+        if !seen.contains(&id) && [INFO, TRACKS, CHAPTERS].contains(&id) {
+            file.seek(SeekFrom::Start(target))?;
+            if read_id(file).ok() == Some(id) { // a seek entry pointing nowhere is skipped
+                if let Some(size) = read_size(file)? {
+                    read_matroska_element(id, &read_body(file, size)?, &mut info);
+                    seen.push(id);
+                }
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// Where the seek head says top level elements are, as absolute positions. Positions past any file are left out
+fn read_seek_head(data: &[u8], segment_start: u64) -> Vec<(u32, u64)> {
+    elements(data).into_iter()
+        .filter(|(id, _)| *id == SEEK)
+        .filter_map(|(_, seek)| Some((uint(child(seek, SEEK_ID)?) as u32, segment_start.checked_add(uint(child(seek, SEEK_POSITION)?))?)))
+        .collect()
+}
+
+fn read_matroska_element(id: u32, data: &[u8], info: &mut MediaInfo) {
+    match id {
+        INFO => {
+            let scale = child(data, TIMECODE_SCALE).map_or(1_000_000, uint); // nanoseconds per unit
+            info.duration = child(data, DURATION).and_then(float).map(|duration| duration * scale as f64 / 1e9);
+        },
+        TRACKS => {
+            info.tracks = elements(data).into_iter()
+                .filter(|(id, _)| *id == TRACK_ENTRY)
+                .map(|(_, entry)| read_track_entry(entry))
+                .collect();
+        },
+        CHAPTERS => {
+            // Only the first edition, the others are alternative cuts
+            if let Some(edition) = child(data, EDITION_ENTRY) {
+                info.chapters = elements(edition).into_iter()
+                    .filter(|(id, _)| *id == CHAPTER_ATOM)
+                    .map(|(_, atom)| Chapter {
+                        start: child(atom, CHAPTER_TIME_START).map_or(0, uint) as f64 / 1e9,
+                        title: child(atom, CHAPTER_DISPLAY).and_then(|display| child(display, CHAP_STRING)).map(text),
+                    })
+                    .collect();
+            }
+        },
+        _ => {},
+    }
+}
+
+fn read_track_entry(entry: &[u8]) -> Track {
+    let video = child(entry, VIDEO);
+    let codec_id = child(entry, CODEC_ID).map(text).unwrap_or_default();
+    Track {
+        kind: match child(entry, TRACK_TYPE).map(uint) {
+            Some(1) => TrackKind::Video,
+            Some(2) => TrackKind::Audio,
+            Some(17) => TrackKind::Subtitle,
+            _ => TrackKind::Other,
+        },
+        codec: matroska_codec(&codec_id),
+        // Matroska's default language is English
+        language: child(entry, LANGUAGE_IETF).or(child(entry, LANGUAGE)).map(text).map_or(Some("eng".to_string()), language),
+        name: child(entry, NAME).map(text).filter(|name| !name.is_empty()),
+        width: video.and_then(|video| child(video, PIXEL_WIDTH)).map(|width| uint(width) as u32),
+        height: video.and_then(|video| child(video, PIXEL_HEIGHT)).map(|height| uint(height) as u32),
+        channels: child(entry, AUDIO).and_then(|audio| child(audio, CHANNELS)).map(|channels| uint(channels) as u32),
+        default: child(entry, FLAG_DEFAULT).map_or(true, |flag| uint(flag) == 1),
+    }
+}
+
+fn matroska_codec(codec_id: &str) -> String {
+    let name = match codec_id {
+        "V_MPEG4/ISO/AVC" => "H.264",
+        "V_MPEGH/ISO/HEVC" => "HEVC",
+        "V_AV1" => "AV1",
+        "V_VP8" => "VP8",
+        "V_VP9" => "VP9",
+        "V_MPEG4/ISO/ASP" => "MPEG-4",
+        "A_OPUS" => "Opus",
+        "A_FLAC" => "FLAC",
+        "A_AC3" => "AC-3",
+        "A_EAC3" => "E-AC-3",
+        "A_VORBIS" => "Vorbis",
+        "A_MPEG/L3" => "MP3",
+        "A_TRUEHD" => "TrueHD",
+        "S_TEXT/ASS" | "S_ASS" => "ASS",
+        "S_TEXT/SSA" | "S_SSA" => "SSA",
+        "S_TEXT/UTF8" => "SRT",
+        "S_TEXT/WEBVTT" => "WebVTT",
+        "S_HDMV/PGS" => "PGS",
+        "S_VOBSUB" => "VobSub",
+        id if id.starts_with("A_AAC") => "AAC",
+        id if id.starts_with("A_DTS") => "DTS",
+        id if id.starts_with("A_PCM") => "PCM",
+        id => return id.to_string(),
+    };
+    name.to_string()
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    data.get(at..at + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some((be_u32(data, at)? as u64) << 32 | be_u32(data, at + 4)? as u64)
+}
+
+/// The boxes inside an MP4 box read into memory, as type and body
+fn boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut found = vec![];
+    let mut at = 0;
+    while let (Some(size), Some(kind)) = (be_u32(data, at), data.get(at + 4..at + 8)) {
+        let (header, size) = match size {
+            0 => (8, (data.len() - at) as u64),
+            1 => match be_u64(data, at + 8) {
+                Some(size) => (16, size),
+                None => break,
+            },
+            size => (8, size as u64),
+        };
+        if size < header as u64 {
+            break;
+        }
+        let end = (at as u64).saturating_add(size).min(data.len() as u64) as usize;
+        found.push(([kind[0], kind[1], kind[2], kind[3]], &data[at + header..end]));
+        at = end;
+    }
+    found
+}
+
+fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let found = boxes(data).into_iter().find(|(kind, _)| kind == *first).map(|(_, body)| body)?;
+    if rest.is_empty() { Some(found) } else { find_box(found, rest) }
+}
+
+fn probe_mp4(file: &mut File) -> std::io::Result<MediaInfo> {
+    let length = file.metadata()?.len();
+    let mut position = 0;
+    // moov holds the headers, it's at the start or the end with the media (mdat) on the other side
+    while position + 8 <= length {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 16];
+        file.read_exact(&mut header[..8])?;
+        let (header_size, size) = match be_u32(&header, 0).unwrap_or(0) {
+            0 => (8, length - position),
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                (16, be_u64(&header, 8).unwrap_or(0))
+            },
+            size => (8, size as u64),
+        };
+        if size < header_size {
+            return Err(invalid("Broken MP4 box"));
+        }
+        if &header[4..8] == b"moov" {
+            file.seek(SeekFrom::Start(position + header_size))?;
+            return Ok(read_moov(&read_body(file, size - header_size)?));
+        }
+        position = position.checked_add(size).ok_or_else(|| invalid("Broken MP4 box"))?;
+    }
+    Err(invalid("No MP4 movie header, the file may be incomplete"))
+}
+
+fn read_moov(moov: &[u8]) -> MediaInfo {
+    let duration = find_box(moov, &[b"mvhd"]).and_then(|mvhd| {
+        let (timescale, duration) = match mvhd.first() {
+            Some(1) => (be_u32(mvhd, 20)?, be_u64(mvhd, 24)?),
+            _ => (be_u32(mvhd, 12)?, be_u32(mvhd, 16)? as u64),
+        };
+        Some(duration as f64 / timescale as f64).filter(|_| timescale > 0 && duration != u32::MAX as u64 && duration != u64::MAX)
+    });
+    let tracks = boxes(moov).into_iter()
+        .filter(|(kind, _)| kind == b"trak")
+        .filter_map(|(_, trak)| read_trak(trak))
+        .collect();
+    let chapters = find_box(moov, &[b"udta", b"chpl"]).map(read_chpl).unwrap_or_default();
+    MediaInfo { container: "MP4", duration, tracks, chapters }
+}
+
+fn read_trak(trak: &[u8]) -> Option<Track> {
+    let mdia = find_box(trak, &[b"mdia"])?;
+    let kind = match find_box(mdia, &[b"hdlr"]).and_then(|hdlr| hdlr.get(8..12))? {
+        b"vide" => TrackKind::Video,
+        b"soun" => TrackKind::Audio,
+        b"sbtl" | b"subt" | b"text" | b"clcp" => TrackKind::Subtitle,
+        _ => TrackKind::Other,
+    };
+    // Three letters packed into five bits each
+    let language = find_box(mdia, &[b"mdhd"])
+        .and_then(|mdhd| be_u16(mdhd, if mdhd.first() == Some(&1) { 32 } else { 20 }))
+        .map(|packed| [10, 5, 0].iter().map(|shift| (((packed >> shift) & 0x1F) as u8 + 0x60) as char).collect::<String>())
+        .and_then(language);
+    let enabled = find_box(trak, &[b"tkhd"]).and_then(|tkhd| be_u32(tkhd, 0)).map_or(true, |flags| flags & 1 == 1);
+
+    // The first sample description says what the codec is and its picture size or channels
+    let entry = find_box(mdia, &[b"minf", b"stbl", b"stsd"]).and_then(|stsd| stsd.get(8..)).and_then(|entries| boxes(entries).into_iter().next());
+    let (format, sample) = match entry {
+        Some((format, sample)) => (format, sample),
+        None => (*b"    ", &[][..]),
+    };
+    let (width, height, channels) = match kind {
+        TrackKind::Video => (be_u16(sample, 24).map(u32::from), be_u16(sample, 26).map(u32::from), None),
+        TrackKind::Audio => (None, None, be_u16(sample, 16).map(u32::from)),
+        _ => (None, None, None),
+    };
+    Some(Track { kind, codec: mp4_codec(&format), language, name: None, width, height, channels, default: enabled })
+}
+
+/// Nero style chapters, what most tools write into MP4 files
+fn read_chpl(chpl: &[u8]) -> Vec<Chapter> {
+    let mut at = if chpl.first() == Some(&1) { 8 } else { 4 }; // version 1 has four more reserved bytes
+    let count = chpl.get(at).copied().unwrap_or(0);
+    at += 1;
+    let mut chapters = vec![];
+    for _ in 0..count {
+        let start = match be_u64(chpl, at) {
+            Some(start) => start,
+            None => break,
+        };
+        let length = chpl.get(at + 8).copied().unwrap_or(0) as usize;
+        let title = chpl.get(at + 9..at + 9 + length).map(text).filter(|title| !title.is_empty());
+        chapters.push(Chapter { start: start as f64 / 1e7, title }); // in 100 ns units
+        at += 9 + length;
+    }
+    chapters
+}
+
+fn mp4_codec(format: &[u8; 4]) -> String {
+    let name = match format {
+        b"avc1" | b"avc3" => "H.264",
+        b"hvc1" | b"hev1" => "HEVC",
+        b"av01" => "AV1",
+        b"vp09" => "VP9",
+        b"mp4v" => "MPEG-4",
+        b"mp4a" => "AAC",
+        b"ac-3" => "AC-3",
+        b"ec-3" => "E-AC-3",
+        b"Opus" => "Opus",
+        b"fLaC" => "FLAC",
+        b"tx3g" => "Timed Text",
+        b"wvtt" => "WebVTT",
+        b"c608" => "CEA-608",
+        other => return String::from_utf8_lossy(other).trim().to_string(),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const DOC_TYPE: u32 = 0x4282;
+
+    /// Probes `content` written to a file called `name` in a folder of its own, which is gone afterwards
+    fn probe_fixture(name: &str, content: &[u8]) -> std::io::Result<MediaInfo> {
+        let dir = std::env::temp_dir().join(format!("anime-cli-probe-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        let info = probe(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        info
+    }
+
+    /// A Matroska element, always with an eight byte size
+    fn el(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut element: Vec<u8> = id.to_be_bytes().iter().copied().skip_while(|byte| *byte == 0).collect();
+        element.push(0x01);
+        element.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        element.extend_from_slice(body);
+        element
+    }
+
+    fn uint_el(id: u32, value: u64) -> Vec<u8> {
+        el(id, &value.to_be_bytes())
+    }
+
+    fn master(id: u32, children: &[Vec<u8>]) -> Vec<u8> {
+        el(id, &children.concat())
+    }
+
+    fn mkv_track(kind: u64, codec: &str, extra: &[Vec<u8>]) -> Vec<u8> {
+        let mut children = vec![uint_el(TRACK_TYPE, kind), el(CODEC_ID, codec.as_bytes())];
+        children.extend_from_slice(extra);
+        master(TRACK_ENTRY, &children)
+    }
+
+    fn chapter_atom(start_ns: u64, title: &str) -> Vec<u8> {
+        master(CHAPTER_ATOM, &[uint_el(CHAPTER_TIME_START, start_ns), master(CHAPTER_DISPLAY, &[el(CHAP_STRING, title.as_bytes())])])
+    }
+
+    fn mkv_parts() -> (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>) {
+        let info = master(INFO, &[uint_el(TIMECODE_SCALE, 1_000_000), el(DURATION, &1_420_500.0f64.to_be_bytes())]);
+        let tracks = master(TRACKS, &[
+            mkv_track(1, "V_MPEGH/ISO/HEVC", &[master(VIDEO, &[uint_el(PIXEL_WIDTH, 1920), uint_el(PIXEL_HEIGHT, 1080)])]),
+            mkv_track(2, "A_AAC/MPEG4/LC", &[el(LANGUAGE, b"jpn"), master(AUDIO, &[uint_el(CHANNELS, 2)])]),
+            mkv_track(17, "S_TEXT/ASS", &[el(LANGUAGE, b"eng"), el(LANGUAGE_IETF, b"en-US"), el(NAME, b"Full Subs"), uint_el(FLAG_DEFAULT, 0)]),
+        ]);
+        let chapters = master(CHAPTERS, &[master(EDITION_ENTRY, &[chapter_atom(0, "Opening"), chapter_atom(90_000_000_000, "Part A")])]);
+        let cluster = el(CLUSTER, &[0; 16]);
+        (info, tracks, chapters, cluster)
+    }
+
+    fn mkv(doc_type: &str, segment: &[u8]) -> Vec<u8> {
+        [el(EBML, &el(DOC_TYPE, doc_type.as_bytes())), segment.to_vec()].concat()
+    }
+
+    fn check_mkv_episode(info: &MediaInfo) {
+        assert_eq!(info.container, "Matroska");
+        assert!((info.duration.unwrap() - 1420.5).abs() < 0.001);
+        assert_eq!(info.resolution(), Some(1080));
+        let video = info.video().unwrap();
+        assert_eq!((video.codec.as_str(), video.width, video.height, video.language.as_deref()), ("HEVC", Some(1920), Some(1080), Some("eng")));
+        let audio = info.tracks_of(TrackKind::Audio);
+        assert_eq!((audio[0].codec.as_str(), audio[0].language.as_deref(), audio[0].channels), ("AAC", Some("jpn"), Some(2)));
+        let subtitles = info.tracks_of(TrackKind::Subtitle);
+        assert_eq!((subtitles[0].codec.as_str(), subtitles[0].language.as_deref(), subtitles[0].name.as_deref()), ("ASS", Some("en-US"), Some("Full Subs")));
+        assert!(audio[0].default && !subtitles[0].default);
+        let chapters: Vec<(f64, Option<&str>)> = info.chapters.iter().map(|chapter| (chapter.start, chapter.title.as_deref())).collect();
+        assert_eq!(chapters, vec![(0.0, Some("Opening")), (90.0, Some("Part A"))]);
+        assert_eq!(info.summary(), "23:40 | 1920x1080 HEVC | audio jpn | subtitles en-US | 2 chapters");
+    }
+
+    #[test]
+    fn matroska_headers() {
+        let (info, tracks, chapters, cluster) = mkv_parts();
+        check_mkv_episode(&probe_fixture("episode.mkv", &mkv("matroska", &el(SEGMENT, &[info, tracks, chapters, cluster].concat()))).unwrap());
+    }
+
+    #[test]
+    fn matroska_chapters_after_the_clusters() {
+        let (info, tracks, chapters, cluster) = mkv_parts();
+        let seek_head = |position: u64| master(SEEK_HEAD, &[master(SEEK, &[el(SEEK_ID, &CHAPTERS.to_be_bytes()), uint_el(SEEK_POSITION, position)])]);
+        let position = (seek_head(0).len() + info.len() + tracks.len() + cluster.len()) as u64;
+        let seek = mkv("matroska", &el(SEGMENT, &[seek_head(position), info, tracks, cluster, chapters].concat()));
+        check_mkv_episode(&probe_fixture("seek.mkv", &seek).unwrap());
+    }
+
+    #[test]
+    fn webm_with_unknown_segment_size() {
+        let info = master(INFO, &[el(DURATION, &5000.0f32.to_be_bytes())]);
+        let tracks = master(TRACKS, &[
+            mkv_track(1, "V_VP9", &[master(VIDEO, &[uint_el(PIXEL_WIDTH, 854), uint_el(PIXEL_HEIGHT, 480)])]),
+            mkv_track(2, "A_OPUS", &[el(LANGUAGE, b"und")]),
+        ]);
+        let segment = [vec![0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], info, tracks].concat();
+        let info = probe_fixture("clip.webm", &mkv("webm", &segment)).unwrap();
+        assert!((info.duration.unwrap() - 5.0).abs() < 0.001);
+        assert_eq!(info.resolution(), Some(480));
+        assert_eq!(info.video().unwrap().codec, "VP9");
+        let audio = info.tracks_of(TrackKind::Audio);
+        assert_eq!((audio[0].codec.as_str(), audio[0].language.as_deref()), ("Opus", None));
+        assert!(info.chapters.is_empty());
+    }
+
+    fn bx(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        [&(body.len() as u32 + 8).to_be_bytes()[..], &kind[..], body].concat()
+    }
+
+    fn mp4_track(handler: &[u8; 4], language: &str, enabled: bool, format: &[u8; 4], sample: &[u8]) -> Vec<u8> {
+        let tkhd = bx(b"tkhd", &[&(if enabled { 3u32 } else { 0 }).to_be_bytes()[..], &[0; 80]].concat());
+        let packed = language.bytes().fold(0u16, |packed, letter| (packed << 5) | (letter - 0x60) as u16);
+        let mdhd = bx(b"mdhd", &[&[0; 20][..], &packed.to_be_bytes(), &[0; 2]].concat());
+        let hdlr = bx(b"hdlr", &[&[0; 8][..], &handler[..], &[0; 12]].concat());
+        let stsd = bx(b"stsd", &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &bx(format, sample)].concat());
+        let minf = bx(b"minf", &bx(b"stbl", &stsd));
+        bx(b"trak", &[tkhd, bx(b"mdia", &[mdhd, hdlr, minf].concat())].concat())
+    }
+
+    fn moov() -> Vec<u8> {
+        let mvhd = bx(b"mvhd", &[&[0; 12][..], &1000u32.to_be_bytes(), &1_420_500u32.to_be_bytes(), &[0; 80]].concat());
+        let video = mp4_track(b"vide", "und", true, b"avc1", &[&[0; 24][..], &1280u16.to_be_bytes(), &720u16.to_be_bytes(), &[0; 50]].concat());
+        let audio = mp4_track(b"soun", "jpn", true, b"mp4a", &[&[0; 16][..], &2u16.to_be_bytes(), &[0; 10]].concat());
+        let subtitles = mp4_track(b"sbtl", "eng", false, b"tx3g", &[0; 8]);
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, 2];
+        for (start, title) in &[(0u64, "Opening"), (900_000_000, "Part A")] {
+            chpl.extend_from_slice(&start.to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        bx(b"moov", &[mvhd, video, audio, subtitles, bx(b"udta", &bx(b"chpl", &chpl))].concat())
+    }
+
+    fn check_mp4_episode(info: &MediaInfo) {
+        assert_eq!(info.container, "MP4");
+        assert!((info.duration.unwrap() - 1420.5).abs() < 0.001);
+        assert_eq!(info.resolution(), Some(720));
+        let video = info.video().unwrap();
+        assert_eq!((video.codec.as_str(), video.width, video.height, video.language.as_deref()), ("H.264", Some(1280), Some(720), None));
+        let audio = info.tracks_of(TrackKind::Audio);
+        assert_eq!((audio[0].codec.as_str(), audio[0].language.as_deref(), audio[0].channels), ("AAC", Some("jpn"), Some(2)));
+        let subtitles = info.tracks_of(TrackKind::Subtitle);
+        assert_eq!((subtitles[0].codec.as_str(), subtitles[0].language.as_deref(), subtitles[0].default), ("Timed Text", Some("eng"), false));
+        let chapters: Vec<(f64, Option<&str>)> = info.chapters.iter().map(|chapter| (chapter.start, chapter.title.as_deref())).collect();
+        assert_eq!(chapters, vec![(0.0, Some("Opening")), (90.0, Some("Part A"))]);
+    }
+
+    #[test]
+    fn mp4_headers_before_and_after_the_media() {
+        let ftyp = bx(b"ftyp", b"isom\0\0\0\0isom");
+        let mdat = bx(b"mdat", &[0; 32]);
+        check_mp4_episode(&probe_fixture("start.mp4", &[ftyp.clone(), moov(), mdat.clone()].concat()).unwrap());
+        check_mp4_episode(&probe_fixture("end.mp4", &[ftyp, mdat, moov()].concat()).unwrap());
+    }
+
+    #[test]
+    fn truncated_files() {
+        let (info, tracks, chapters, cluster) = mkv_parts();
+        let whole = mkv("matroska", &el(SEGMENT, &[info.clone(), tracks, chapters, cluster].concat()));
+        let cut = whole.len() - 100;
+        assert!(probe_fixture("cut.mkv", &whole[..cut]).is_err());
+        assert!(probe_fixture("header.mkv", &whole[..20]).is_err());
+
+        let ftyp = bx(b"ftyp", b"isom\0\0\0\0isom");
+        let moov = moov();
+        assert!(probe_fixture("cut.mp4", &[&ftyp[..], &moov[..moov.len() / 2]].concat()).is_err());
+        assert!(probe_fixture("ftyp.mp4", &ftyp).is_err());
+        assert!(probe_fixture("short.mkv", &[0x1A, 0x45, 0xDF]).is_err());
+    }
+
+    #[test]
+    fn sizes_and_positions_past_the_end() {
+        let (info, tracks, chapters, cluster) = mkv_parts();
+        let seek_head = master(SEEK_HEAD, &[master(SEEK, &[el(SEEK_ID, &CHAPTERS.to_be_bytes()), uint_el(SEEK_POSITION, u64::MAX - 8)])]);
+        let bogus_seek = mkv("matroska", &el(SEGMENT, &[seek_head, info, tracks, cluster, chapters].concat()));
+        let probed = probe_fixture("seek-past-end.mkv", &bogus_seek).unwrap();
+        assert!(probed.chapters.is_empty() && probed.resolution() == Some(1080));
+
+        let ftyp = bx(b"ftyp", b"isom\0\0\0\0isom");
+        let huge = |size: u64| [&1u32.to_be_bytes()[..], b"free", &size.to_be_bytes(), &[0; 16]].concat();
+        let overflowing = [ftyp.clone(), huge(u64::MAX - 4), moov()].concat();
+        assert_eq!(probe_fixture("overflow.mp4", &overflowing).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(probe_fixture("past-end.mp4", &[ftyp, huge(1 << 40), moov()].concat()).is_err());
+    }
+
+    #[test]
+    fn garbage() {
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        assert_eq!(probe_fixture("noise.mkv", &noise).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(probe_fixture("text.mp4", b"this is not a video at all").is_err());
+        assert!(probe_fixture("broken.mkv", &[0x1A, 0x45, 0xDF, 0xA3, 0x00, 0x00, 0x00, 0x00, 0x00]).is_err());
+        assert!(probe_fixture("broken.mp4", &[0, 0, 0, 4, b'f', b't', b'y', b'p', 0, 0]).is_err());
+        assert!(probe(Path::new("/nonexistent/anime-cli/episode.mkv")).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::anime_config;
+use crate::anime_episode::EpisodeSpec;
+
+const QUEUE_FILE: &str = "queue";
+
+/// A download waiting for `anime-cli queue run`
+#[derive(Clone)]
+pub struct QueueEntry {
+    pub query: String,
+    pub episodes: Option<EpisodeSpec>,
+    pub season: Option<u32>,
+    pub resolution: Option<u16>,
+}
+
+impl fmt::Display for QueueEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.query)?;
+        if let Some(resolution) = self.resolution {
+            write!(f, " {}p", resolution)?;
+        }
+        if let Some(season) = self.season {
+            write!(f, ", season {}", season)?;
+        }
+        match &self.episodes {
+            Some(episodes) => write!(f, ", episodes {}", episodes),
+            None => Ok(()),
+        }
+    }
+}
+
+fn queue_path() -> Option<PathBuf> {
+    anime_config::data_dir().map(|dir| dir.join(QUEUE_FILE))
+}
+
+/// Entries in the order they were added, one per line as tab separated fields
+pub fn load() -> Vec<QueueEntry> {
+    let content = queue_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    content.lines().filter_map(parse_line).collect()
+}
+
+/// Query, episodes, season and resolution. Lines from before seasons were queued have no season field
+fn parse_line(line: &str) -> Option<QueueEntry> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let (season, resolution) = match fields.len() {
+        3 => ("", fields[2]),
+        4 => (fields[2], fields[3]),
+        _ => return None,
+    };
+    if fields[0].is_empty() {
+        return None;
+    }
+    Some(QueueEntry {
+        query: fields[0].to_string(),
+        episodes: fields[1].parse().ok(),
+        season: season.parse().ok(),
+        resolution: resolution.parse().ok(),
+    })
+}
+
+pub fn save(entries: &[QueueEntry]) -> std::io::Result<()> {
+    let path = queue_path()
+        .ok_or(std::io::Error::new(std::io::ErrorKind::NotFound, "Could not find a data directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::File::create(path)?;
+    for entry in entries {
+        let query = entry.query.replace(|c: char| c == '\t' || c == '\n' || c == '\r', " ");
+        let episodes = entry.episodes.as_ref().map(|e| e.to_string()).unwrap_or_default();
+        let season = entry.season.map(|s| s.to_string()).unwrap_or_default();
+        let resolution = entry.resolution.map(|r| r.to_string()).unwrap_or_default();
+        writeln!(file, "{}\t{}\t{}\t{}", query, episodes, season, resolution)?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lines_with_and_without_season() {
+        let entry = parse_line("Show\t1-5\t2\t1080").unwrap();
+        assert_eq!((entry.query.as_str(), entry.season, entry.resolution), ("Show", Some(2), Some(1080)));
+        assert_eq!(entry.episodes.map(|e| e.to_string()).as_deref(), Some("1-5"));
+
+        let entry = parse_line("Show\tlatest\t720").unwrap();
+        assert_eq!((entry.query.as_str(), entry.season, entry.resolution), ("Show", None, Some(720)));
+        assert_eq!(entry.episodes.map(|e| e.to_string()).as_deref(), Some("latest"));
+
+        let entry = parse_line("Show\t\t\t").unwrap();
+        assert!(entry.episodes.is_none() && entry.season.is_none() && entry.resolution.is_none());
+
+        assert!(parse_line("\t1\t\t720").is_none());
+        assert!(parse_line("Show\t1").is_none());
+        assert!(parse_line("Show\t1\t2\t720\textra").is_none());
+    }
+}
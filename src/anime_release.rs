@@ -0,0 +1,191 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::anime_episode::Episode;
+use crate::anime_part;
+
+lazy_static! {
+    static ref GROUP_REGEX: Regex = Regex::new(r"^\s*\[([^\]]+)\]").unwrap();
+    static ref TAG_REGEX: Regex = Regex::new(r"[\[(]([^\])]*)[\])]").unwrap();
+    static ref RESOLUTION_REGEX: Regex = Regex::new(r"(?i)\b(?:\d{3,4}x)?(\d{3,4})p?\b").unwrap();
+    static ref CODEC_REGEX: Regex = Regex::new(r"(?i)\b(x264|x265|h\.?264|h\.?265|hevc|avc|av1|vp9|xvid)\b").unwrap();
+    // "Show S2", "Show S02E05", "Show 2nd Season", "Show Season 2", the first group is the marker, the second the number
+    static ref SEASON_REGEXES: Vec<Regex> = vec![
+        Regex::new(r"(?i)\b(S(\d{1,2}))(?:E\d|\b)").unwrap(),
+        Regex::new(r"(?i)\b((\d{1,2})(?:st|nd|rd|th)\s+Season)\b").unwrap(),
+        Regex::new(r"(?i)\b(Season\s*(\d{1,2}))\b").unwrap(),
+    ];
+    // "Show - 01", "Show - 12.5v2", "Show - OVA 2", "Show Episode 5" or "Show E05"
+    static ref EPISODE_REGEXES: Vec<Regex> = vec![
+        Regex::new(r"(?i)\s-\s+(\d{1,4}(?:\.5)?)(?:v(\d))?(?:\s|$)").unwrap(),
+        Regex::new(r"(?i)\b((?:OVA|OAD|ONA|SP|Special)(?:\s*-?\s*\d{1,3})?)(?:v(\d))?\b").unwrap(),
+        Regex::new(r"(?i)\b(?:E|Ep|Episode)\.?\s*(\d{1,4}(?:\.5)?)(?:v(\d))?\b").unwrap(),
+    ];
+    // "Show 01" at the end, which could just as well be a title like "Mob Psycho 100"
+    static ref TRAILING_EPISODE_REGEX: Regex = Regex::new(r"\s(\d{1,4}(?:\.5)?)(?:v(\d))?\s*$").unwrap();
+}
+
+/// What a release name like `[Group] Show - 05v2 [1080p][1A2B3C4D].mkv` tells us
+#[derive(Clone, Debug, PartialEq)]
+pub struct Release {
+    pub group: Option<String>,
+    pub title: String,
+    pub season: Option<u32>,
+    pub episode: Option<Episode>,
+    pub version: Option<u8>,
+    pub resolution: Option<u16>,
+    /// Video codec when the name says, e.g. `HEVC` for `x265`
+    pub codec: Option<String>,
+    pub crc: Option<u32>,
+}
+
+/// Reads a release file name, anything that can't be made out is left empty
+pub fn parse(filename: &str) -> Release {
+    let name = strip_extension(filename);
+    let group = GROUP_REGEX.captures(name).map(|caps| caps[1].trim().to_string());
+
+    let crc = anime_part::crc_from_filename(name);
+    let resolution = TAG_REGEX.captures_iter(name)
+        .find_map(|caps| RESOLUTION_REGEX.captures(caps[1].trim()).and_then(|r| r[1].parse().ok()).filter(|r| *r >= 240));
+
+    let codec = CODEC_REGEX.captures(name).map(|caps| match caps[1].to_lowercase().replace('.', "").as_str() {
+        "x264" | "h264" | "avc" => "H.264".to_string(),
+        "x265" | "h265" | "hevc" => "HEVC".to_string(),
+        "xvid" => "XviD".to_string(),
+        other => other.to_uppercase(),
+    });
+
+    // Tags are out of the way now, so numbers inside them aren't mistaken for episodes
+    let bare = TAG_REGEX.replace_all(name, " ").replace('_', " ");
+    // and the season is out of the way so "Season 2" isn't read as episode 2. S02E05 leaves E05 behind
+    let (bare, season) = split_season(bare.trim());
+    // A bare number at the end is only an episode in a tagged release name or when it's zero-padded
+    let tagged = TAG_REGEX.is_match(name);
+    let trailing = TRAILING_EPISODE_REGEX.captures(&bare).filter(|caps| tagged || (caps[1].len() > 1 && caps[1].starts_with('0')));
+    let found = EPISODE_REGEXES.iter()
+        .filter_map(|regex| regex.captures(&bare))
+        .chain(trailing)
+        .find_map(|caps| caps[1].parse::<Episode>().ok().map(|episode| (episode, caps.get(2), caps.get(0).unwrap().start())));
+    let (title, episode, version) = match found {
+        Some((episode, version, start)) => (&bare[..start], Some(episode), version.and_then(|v| v.as_str().parse().ok())),
+        None => (&bare[..], None, None),
+    };
+
+    Release {
+        group,
+        title: title.trim_matches(|c: char| c.is_whitespace() || c == '-' || c == '.').to_string(),
+        season,
+        episode,
+        version,
+        resolution,
+        codec,
+        crc,
+    }
+}
+
+/// The title and season most of `releases` agree on, so one oddly named release doesn't decide for a whole batch
+pub fn common_title_and_season(releases: &[Release]) -> (Option<String>, Option<u32>) {
+    let title = most_common(releases.iter().map(|release| &release.title).filter(|title| !title.is_empty()));
+    (title.cloned(), most_common(releases.iter().filter_map(|release| release.season)))
+}
+
+/// The value seen most often, the earliest one on a tie
+fn most_common<T: PartialEq>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = vec![];
+    for value in values {
+        match counts.iter_mut().find(|(seen, _)| *seen == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    let highest = counts.iter().map(|(_, count)| *count).max()?;
+    counts.into_iter().find(|(_, count)| *count == highest).map(|(value, _)| value)
+}
+
+/// Takes the season out of a title or query, e.g. `Show 2nd Season` gives `Show` and season 2
+pub fn split_season(text: &str) -> (String, Option<u32>) {
+    let mut rest = text.to_string();
+    let mut season = None;
+    for regex in SEASON_REGEXES.iter() {
+        if let Some(caps) = regex.captures(text) {
+            season = caps[2].parse().ok();
+            let marker = caps.get(1).unwrap();
+            rest = format!("{} {}", &text[..marker.start()], &text[marker.end()..]);
+            break;
+        }
+    }
+
+    let rest = rest.split_whitespace().collect::<Vec<_>>().join(" ");
+    (rest.trim_matches(|c: char| c.is_whitespace() || c == '-' || c == '.').to_string(), season)
+}
+
+fn strip_extension(filename: &str) -> &str {
+    match filename.rfind('.') {
+        Some(dot) if filename.len() - dot <= 5
+            && filename[dot + 1..].chars().all(char::is_alphanumeric)
+            && filename[dot + 1..].chars().any(char::is_alphabetic) => &filename[..dot],
+        _ => filename,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // name, group, title, season, episode, version, resolution
+    type Case = (&'static str, Option<&'static str>, &'static str, Option<u32>, Option<Episode>, Option<u8>, Option<u16>);
+
+    #[test]
+    fn release_names() {
+        let cases: Vec<Case> = vec![
+            ("[Group] Show - 05v2 [1080p][1A2B3C4D].mkv", Some("Group"), "Show", None, Some(Episode::Regular(5)), Some(2), Some(1080)),
+            ("[Group] Show S2 - 03 (720p).mkv", Some("Group"), "Show", Some(2), Some(Episode::Regular(3)), None, Some(720)),
+            ("[Group] Show 2nd Season - 12.5 [1920x1080].mkv", Some("Group"), "Show", Some(2), Some(Episode::Half(12)), None, Some(1080)),
+            ("Show.S01E07.1080p.mkv", None, "Show", Some(1), Some(Episode::Regular(7)), None, None),
+            ("[Group] Show - OVA 2 [480p].mkv", Some("Group"), "Show", None, Some(Episode::Special { kind: "OVA".to_string(), number: Some(2) }), None, Some(480)),
+            ("[Group] Show SP1v3 [1080p].mkv", Some("Group"), "Show", None, Some(Episode::Special { kind: "SP".to_string(), number: Some(1) }), Some(3), Some(1080)),
+            ("[Group] Show Episode 9 [720p].mkv", Some("Group"), "Show", None, Some(Episode::Regular(9)), None, Some(720)),
+            ("[Group] Show 04 [720p].mkv", Some("Group"), "Show", None, Some(Episode::Regular(4)), None, Some(720)),
+            ("Show_Name_01.mkv", None, "Show Name", None, Some(Episode::Regular(1)), None, None),
+            ("Mob Psycho 100 - 01.mkv", None, "Mob Psycho 100", None, Some(Episode::Regular(1)), None, None),
+            ("Mob Psycho 100", None, "Mob Psycho 100", None, None, None, None),
+            ("Steins;Gate 0", None, "Steins;Gate 0", None, None, None, None),
+            ("Show Season 3", None, "Show", Some(3), None, None, None),
+        ];
+        for (name, group, title, season, episode, version, resolution) in cases {
+            let release = parse(name);
+            assert_eq!(release.group.as_deref(), group, "{}", name);
+            assert_eq!(release.title, title, "{}", name);
+            assert_eq!(release.season, season, "{}", name);
+            assert_eq!(release.episode, episode, "{}", name);
+            assert_eq!(release.version, version, "{}", name);
+            assert_eq!(release.resolution, resolution, "{}", name);
+        }
+    }
+
+    #[test]
+    fn codec_and_crc() {
+        let release = parse("[Group] Show - 01 [x265][ABCD1234].mkv");
+        assert_eq!(release.codec.as_deref(), Some("HEVC"));
+        assert_eq!(release.crc, Some(0xABCD1234));
+        let release = parse("[DEADBEEF] Show - 01 (H.264).mkv");
+        assert_eq!(release.codec.as_deref(), Some("H.264"));
+        assert_eq!(release.crc, None);
+    }
+
+    #[test]
+    fn common_title_of_a_batch() {
+        let releases: Vec<Release> = ["[A] Show - 01.mkv", "[B] Show (2019) - 02.mkv", "[A] Show S2 - 03.mkv", "[A] Show S2 - 04.mkv", "[C] Show - 05.mkv"]
+            .iter().map(|name| parse(name)).collect();
+        assert_eq!(common_title_and_season(&releases), (Some("Show".to_string()), Some(2)));
+        assert_eq!(common_title_and_season(&[]), (None, None));
+    }
+
+    #[test]
+    fn seasons_in_queries() {
+        assert_eq!(split_season("Show 2nd Season"), ("Show".to_string(), Some(2)));
+        assert_eq!(split_season("Show S03"), ("Show".to_string(), Some(3)));
+        assert_eq!(split_season("Show Season 2 - Part"), ("Show - Part".to_string(), Some(2)));
+        assert_eq!(split_season("Show"), ("Show".to_string(), None));
+    }
+}
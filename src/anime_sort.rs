@@ -0,0 +1,191 @@
+//! The orders the browser can list shows and episodes in
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+use crate::anime_watch::AnimeListing;
+
+/// How the browser orders listings, `natural` reads numbers as numbers so episode 2 comes before 10
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+    Natural,
+    Name,
+    Modified,
+    Size,
+    Episodes,
+    Watched,
+}
+
+const SORT_ORDERS: &'static [(SortOrder, &'static str)] = &[
+    (SortOrder::Natural, "natural"),
+    (SortOrder::Name, "name"),
+    (SortOrder::Modified, "modified"),
+    (SortOrder::Size, "size"),
+    (SortOrder::Episodes, "episodes"),
+    (SortOrder::Watched, "watched"),
+];
+
+impl SortOrder {
+    /// The order after this one, what the O key in the browser switches to
+    pub fn next(self) -> SortOrder {
+        let i = SORT_ORDERS.iter().position(|(order, _)| *order == self).unwrap_or(0);
+        SORT_ORDERS[(i + 1) % SORT_ORDERS.len()].0
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(text: &str) -> std::result::Result<SortOrder, String> {
+        SORT_ORDERS.iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(text.trim()))
+            .map(|(order, _)| *order)
+            .ok_or(format!("{} is not a sort order, use one of {}", text,
+                           SORT_ORDERS.iter().map(|(_, name)| *name).collect::<Vec<_>>().join(", ")))
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = SORT_ORDERS.iter().find(|(order, _)| order == self).map_or("natural", |(_, name)| *name);
+        write!(f, "{}", name)
+    }
+}
+
+/// Puts `listings` in `order`. Everything but `name` and `natural` has the biggest or most recent first,
+/// ties and listings without a value fall back to the natural order
+pub fn sort_listings(listings: &mut [AnimeListing], order: SortOrder) {
+    listings.sort_by(|a, b| {
+        let by = match order {
+            SortOrder::Natural => Ordering::Equal,
+            SortOrder::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortOrder::Modified => b.modified.cmp(&a.modified),
+            SortOrder::Size => b.size.cmp(&a.size),
+            SortOrder::Episodes => b.episode_count.cmp(&a.episode_count),
+            SortOrder::Watched => b.last_watched.cmp(&a.last_watched),
+        };
+        by.then_with(|| natural_cmp(&a.name, &b.name))
+    });
+}
+
+/// Compares names ignoring case, with runs of digits compared as numbers so `Show - 2` comes before `Show - 10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn digits(chars: &mut Peekable<Chars>) -> String {
+        let mut run = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            run.push(*c);
+            chars.next();
+        }
+        run
+    }
+
+    let (a_lower, b_lower) = (a.to_lowercase(), b.to_lowercase());
+    let (mut a_chars, mut b_chars) = (a_lower.chars().peekable(), b_lower.chars().peekable());
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (digits(&mut a_chars), digits(&mut b_chars));
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let by = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if by != Ordering::Equal {
+                    return by;
+                }
+            },
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a_chars.next();
+                b_chars.next();
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn numbers_compare_as_numbers() {
+        assert_eq!(natural_cmp("ep2", "ep10"), Ordering::Less);
+        assert_eq!(natural_cmp("ep10", "ep2"), Ordering::Greater);
+        assert_eq!(sorted(&["Show - 10.mkv", "Show - 2.mkv", "Show - 1.mkv"]), vec!["Show - 1.mkv", "Show - 2.mkv", "Show - 10.mkv"]);
+        assert_eq!(sorted(&["Season 10", "Season 9", "Season 1"]), vec!["Season 1", "Season 9", "Season 10"]);
+    }
+
+    #[test]
+    fn leading_zeros() {
+        assert_eq!(natural_cmp("ep02", "ep10"), Ordering::Less);
+        assert_eq!(natural_cmp("ep007", "ep7 "), Ordering::Less);
+        assert_ne!(natural_cmp("ep002", "ep2"), Ordering::Equal); // the same number, but different names
+        assert_eq!(natural_cmp("ep02 b", "ep2 a"), Ordering::Greater);
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        assert_eq!(natural_cmp("apple", "Banana"), Ordering::Less);
+        assert_eq!(natural_cmp("Banana", "apple"), Ordering::Greater);
+        assert_eq!(sorted(&["b 2", "B 10", "a 3"]), vec!["a 3", "b 2", "B 10"]);
+        assert_ne!(natural_cmp("Show", "show"), Ordering::Equal);
+        assert_eq!(natural_cmp("Show", "Show"), Ordering::Equal);
+    }
+
+    #[test]
+    fn equal_numeric_prefixes() {
+        assert_eq!(natural_cmp("12 b", "12 a"), Ordering::Greater);
+        assert_eq!(natural_cmp("12", "12 a"), Ordering::Less);
+        assert_eq!(natural_cmp("1.5", "1.10"), Ordering::Less);
+        assert_eq!(natural_cmp("v1x2", "v1x10"), Ordering::Less);
+    }
+
+    fn listing(name: &str, size: u64, episode_count: u32) -> AnimeListing {
+        AnimeListing {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            is_dir: true,
+            episode_count,
+            season_count: 0,
+            watched_count: 0,
+            is_media: false,
+            watch_state: None,
+            size,
+            modified: None,
+            last_watched: None,
+        }
+    }
+
+    #[test]
+    fn sorts_listings() {
+        let mut listings = vec![listing("Show 10", 5, 3), listing("show 2", 5, 12), listing("Other", 9, 3)];
+        let names = |listings: &[AnimeListing]| listings.iter().map(|listing| listing.name.clone()).collect::<Vec<_>>();
+        sort_listings(&mut listings, SortOrder::Natural);
+        assert_eq!(names(&listings), vec!["Other", "show 2", "Show 10"]);
+        sort_listings(&mut listings, SortOrder::Name);
+        assert_eq!(names(&listings), vec!["Other", "Show 10", "show 2"]);
+        sort_listings(&mut listings, SortOrder::Size);
+        assert_eq!(names(&listings), vec!["Other", "show 2", "Show 10"]);
+        sort_listings(&mut listings, SortOrder::Episodes);
+        assert_eq!(names(&listings), vec!["show 2", "Other", "Show 10"]);
+    }
+
+    #[test]
+    fn orders_by_name() {
+        assert_eq!("Size".parse::<SortOrder>(), Ok(SortOrder::Size));
+        assert!("biggest".parse::<SortOrder>().is_err());
+        assert_eq!(SortOrder::Watched.next(), SortOrder::Natural);
+        assert_eq!(SortOrder::Modified.to_string(), "modified");
+    }
+}
@@ -0,0 +1,247 @@
+extern crate chrono;
+extern crate dirs;
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::{anime_config, anime_format, anime_history};
+
+const RECORD_FILE: &str = "trashed"; // what anime-cli put in a trash, the rest is left alone
+const INFO_SUFFIX: &str = ".trashinfo";
+
+/// Something anime-cli moved to a trash
+#[derive(Clone)]
+pub struct TrashEntry {
+    /// The trash it is in, the home one or `.Trash-<uid>` at the top of another drive
+    pub dir: PathBuf,
+    /// Name in the trash, the original one unless something by that name was already there
+    pub name: OsString,
+    pub original: PathBuf,
+    /// `2020-05-17T21:04:00`, local time as the trash spec has it
+    pub deleted: String,
+}
+
+impl TrashEntry {
+    /// Where the item is while it's in the trash
+    pub fn path(&self) -> PathBuf {
+        self.dir.join("files").join(&self.name)
+    }
+}
+
+/// The `trash` setting if there is one, otherwise the freedesktop trash in the user's data folder
+/// (`~/.local/share/Trash`) on Linux and the BSDs, and a trash folder next to the queue elsewhere
+pub fn trash_dir() -> Option<PathBuf> {
+    if let Some(dir) = anime_config::Config::load().get("trash") {
+        return Some(PathBuf::from(dir));
+    }
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        dirs::data_dir().map(|dir| dir.join("Trash"))
+    } else {
+        anime_config::data_dir().map(|dir| dir.join("trash"))
+    }
+}
+
+fn not_found() -> Error {
+    Error::new(ErrorKind::NotFound, "Could not find a trash folder, set one with anime-cli config set trash <folder>")
+}
+
+/// What no trash can take comes back as `Unsupported`, the caller can offer to delete it for good instead
+fn no_trash(original: &Path, reason: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::Unsupported, format!("No trash can take {}: {}", original.display(), reason))
+}
+
+/// Whether a rename failed because the two paths are on different drives
+fn crosses_devices(e: &Error) -> bool {
+    // EXDEV on Linux, macOS and the BSDs, ERROR_NOT_SAME_DEVICE on Windows
+    e.raw_os_error() == Some(if cfg!(windows) { 17 } else { 18 })
+}
+
+/// The home trash when `original` is on the same drive, otherwise `.Trash-<uid>` at the top of its drive
+/// as the freedesktop spec has it, along with that top folder
+#[cfg(unix)]
+fn trash_for(original: &Path) -> std::io::Result<(PathBuf, Option<PathBuf>)> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let device = fs::symlink_metadata(original)?.dev();
+    let device_of = |path: &Path| path.ancestors().find_map(|dir| fs::metadata(dir).ok()).map(|metadata| metadata.dev());
+    if let Some(home) = trash_dir().filter(|home| device_of(home) == Some(device)) {
+        return Ok((home, None));
+    }
+
+    let top = original.ancestors().skip(1)
+        .take_while(|dir| fs::metadata(dir).map_or(false, |metadata| metadata.dev() == device))
+        .last()
+        .ok_or_else(|| no_trash(original, "it is the top of its drive"))?
+        .to_path_buf();
+    // The owner of /proc/self is who we run as, the home folder's owner where there is no /proc
+    let uid = match fs::metadata("/proc/self") {
+        Ok(metadata) => metadata.uid(),
+        Err(_) => fs::metadata(dirs::home_dir().ok_or_else(not_found)?)?.uid(),
+    };
+    let dir = top.join(format!(".Trash-{}", uid));
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(no_trash(original, format!("could not make {}: {}", dir.display(), e))),
+        _ => {},
+    }
+    // A link or someone else's folder would hand what's trashed to somebody else
+    let metadata = fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid {
+        return Err(no_trash(original, format!("{} is not a folder of ours", dir.display())));
+    }
+    Ok((dir, Some(top)))
+}
+
+#[cfg(not(unix))]
+fn trash_for(_original: &Path) -> std::io::Result<(PathBuf, Option<PathBuf>)> {
+    trash_dir().map(|dir| (dir, None)).ok_or_else(not_found)
+}
+
+fn info_path(dir: &Path, name: &OsStr) -> PathBuf {
+    let mut file = name.to_os_string();
+    file.push(INFO_SUFFIX);
+    dir.join("info").join(file)
+}
+
+fn record_path() -> Option<PathBuf> {
+    anime_config::data_dir().map(|dir| dir.join(RECORD_FILE))
+}
+
+/// The trashed items anime-cli keeps track of, one percent-encoded path per line.
+/// Lines from older versions are bare names in the home trash
+fn recorded() -> Vec<PathBuf> {
+    let home = trash_dir();
+    record_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| match anime_format::decode_path(line) {
+            path if path.is_absolute() => Some(path),
+            _ => home.as_ref().map(|dir| dir.join("files").join(line)),
+        })
+        .collect()
+}
+
+fn save_record(paths: &[PathBuf]) -> std::io::Result<()> {
+    let path = record_path().ok_or_else(not_found)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::File::create(path)?;
+    for path in paths {
+        writeln!(file, "{}", anime_format::encode_path(path))?;
+    }
+    file.flush()
+}
+
+/// Moves `path` into a trash along with a `.trashinfo` saying where it came from.
+/// Items on another drive than the home trash go to a trash at the top of that drive,
+/// when there can't be one the error is `ErrorKind::Unsupported`
+pub fn trash(path: &Path) -> std::io::Result<TrashEntry> {
+    let original = anime_history::absolute_path(path);
+    let base = original.file_name().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Nothing to trash"))?.to_os_string();
+    let (dir, top) = trash_for(&original)?;
+    let (files, info) = (dir.join("files"), dir.join("info"));
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+
+    // The info file is created first and exclusively, so two deletions can't pick the same name
+    let mut name = base.clone();
+    let mut count = 1;
+    let mut info_file = loop {
+        let info_path = info_path(&dir, &name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(file) if !files.join(&name).exists() => break file,
+            Ok(_) => fs::remove_file(&info_path)?, // taken by something without an info file
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {},
+            Err(e) => return Err(e),
+        }
+        count += 1;
+        name = base.clone();
+        name.push(format!(".{}", count));
+    };
+
+    let deleted = Local::now().format("%Y-%m-%dT%H:%M:%S").to_string();
+    // A trash at the top of a drive keeps paths relative to it, so they survive the drive being mounted elsewhere
+    let recorded_path = top.as_ref().and_then(|top| original.strip_prefix(top).ok()).unwrap_or(&original);
+    writeln!(info_file, "[Trash Info]\nPath={}\nDeletionDate={}", anime_format::encode_path(recorded_path), deleted)?;
+    let entry = TrashEntry { dir, name, original, deleted };
+    if let Err(e) = fs::rename(&entry.original, entry.path()) {
+        let _ = fs::remove_file(info_path(&entry.dir, &entry.name));
+        return Err(if crosses_devices(&e) {
+            no_trash(&entry.original, format!("{} is on another drive", entry.dir.display()))
+        } else {
+            Error::new(e.kind(), format!("Could not move {} to the trash in {}: {}", entry.original.display(), entry.dir.display(), e))
+        });
+    }
+
+    let _ = anime_history::move_watch_states(&entry.original, &entry.path()); // so a restore brings them back
+
+    let mut paths = recorded();
+    paths.push(entry.path());
+    save_record(&paths)?;
+    Ok(entry)
+}
+
+/// Deletes `path` for good, for what no trash can take
+pub fn delete(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// What anime-cli trashed that is still in a trash, oldest first
+pub fn list() -> Vec<TrashEntry> {
+    let mut entries: Vec<TrashEntry> = recorded().into_iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+        .filter_map(|path| {
+            let name = path.file_name()?.to_os_string();
+            let dir = path.parent()?.parent()?.to_path_buf();
+            let info = fs::read_to_string(info_path(&dir, &name)).ok()?;
+            let value = |key: &str| info.lines().find(|line| line.starts_with(key)).map(|line| line[key.len()..].trim().to_string());
+            let original = anime_format::decode_path(&value("Path=")?);
+            // Relative to the top of the drive the trash is on
+            let original = if original.is_absolute() { original } else { dir.parent()?.join(original) };
+            Some(TrashEntry { dir, name, original, deleted: value("DeletionDate=").unwrap_or_default() })
+        })
+        .collect();
+    entries.sort_by(|a, b| a.deleted.cmp(&b.deleted));
+    entries
+}
+
+/// Puts `entry` back where it was, as long as nothing took its place in the meantime
+pub fn restore(entry: &TrashEntry) -> std::io::Result<()> {
+    if entry.original.exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, format!("{} already exists", entry.original.display())));
+    }
+    if let Some(parent) = entry.original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(entry.path(), &entry.original)?;
+    let _ = anime_history::move_watch_states(&entry.path(), &entry.original);
+    forget(entry)
+}
+
+/// Deletes everything anime-cli put in a trash for good, returns how many items went
+pub fn empty() -> std::io::Result<usize> {
+    let entries = list();
+    for entry in &entries {
+        delete(&entry.path())?;
+        forget(entry)?;
+    }
+    Ok(entries.len())
+}
+
+fn forget(entry: &TrashEntry) -> std::io::Result<()> {
+    let info = info_path(&entry.dir, &entry.name);
+    if info.exists() {
+        fs::remove_file(info)?;
+    }
+    let paths: Vec<PathBuf> = recorded().into_iter().filter(|path| *path != entry.path()).collect();
+    save_record(&paths)
+}
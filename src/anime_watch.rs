@@ -1,261 +1,80 @@
-#[cfg(feature = "mpv")]
-extern crate mpv;
-extern crate crossterm;
-
-use std::path::PathBuf;
-use std::{fs, thread, env};
-use std::process::exit;
+use std::path::{Path, PathBuf};
+use std::thread;
 use std::ffi::OsStr;
-use std::io::{stdout, Write};
-
-use crossterm::{execute, Result, terminal};
-use crossterm::event::{poll, read, Event, KeyCode};
-use crossterm::cursor::{MoveTo, RestorePosition, SavePosition};
-use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
-use crossterm::style::Print;
 
-use crate::{is_valid_media_file};
-use std::time::Duration;
+use crate::{anime_config, anime_history, anime_organize, anime_part, anime_player, anime_playlist, anime_release, is_valid_media_file};
+use crate::anime_episode::Episode;
+use crate::anime_history::WatchState;
+use crate::anime_release::Release;
+use crate::anime_sort::{self, SortOrder};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+const SEASON_PREFIX: &str = "Season ";
+const SAVE_INTERVAL: Duration = Duration::from_secs(10); // how often the playback position is written down
+const PART_STALL_LIMIT: Duration = Duration::from_secs(120); // how long a .part that stopped growing is waited for
+
+/// What comes next when watching a show in order
+pub enum NextEpisode {
+    /// Downloaded but not watched to the end yet
+    Play(PathBuf),
+    /// The episode after the last watched one isn't on disk. `dir` is where the watched one is
+    Download { title: String, season: u32, episode: u32, resolution: Option<u16>, dir: PathBuf },
+    /// Everything here is watched and there's no regular episode to continue from
+    Nothing,
+}
 
-const LISTINGS_PER_PAGE: usize = 7;
+/// An episode file found under a series folder
+struct LocalEpisode {
+    path: PathBuf,
+    season: u32,
+    release: Release,
+}
 
+/// A show folder or a loose episode in the anime directory
 #[derive(Clone)]
-struct AnimeListing {
+pub struct AnimeListing {
     pub name: String,
     pub path: PathBuf,
     pub is_dir: bool,
     pub episode_count: u32,
+    pub season_count: u32,
+    pub watched_count: u32,
     pub is_media: bool,
+    pub watch_state: Option<WatchState>,
+    /// Bytes in the file, or in everything under the folder
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// When anything in it was last played
+    pub last_watched: Option<i64>,
 }
 
-pub fn browse_anime_listings() -> Result<()> {
-    execute!(stdout(), EnterAlternateScreen)?;
-    let anime_dir = match env::current_dir() {
-        Ok(path) => path,
-        Err(_) => { eprintln!("Do you have permission to modify this folder?"); exit(1) }
-    };
-
-    let mut sub_dir = anime_dir.clone();
-    let mut prefix = anime_dir.clone();
-    prefix.pop();
-
-    let mut current_page = 1;
-    let mut current_selected_row = 0;
-    let mut show_empty_folders = false; // allow users to see and delete empty folders and non media items
-
-    'main: loop {
-        let listings = get_anime_listings(sub_dir.clone(), show_empty_folders);
-        let mut max_pages = listings.len() / LISTINGS_PER_PAGE;
-        let mut listings_on_last_page= LISTINGS_PER_PAGE;
-        if listings.len() % LISTINGS_PER_PAGE != 0 {
-            max_pages += 1;
-            listings_on_last_page = listings.len() % LISTINGS_PER_PAGE;
-        } else if listings.len() == 0 {
-            max_pages = 1;
-            listings_on_last_page = 1;
-        }
-
-        let tip_cursor_offset = 5; //Lines of text at top
-        execute!(stdout(), MoveTo(1, tip_cursor_offset), SavePosition)?;
-
-        let mut clear_input_buffer = false;
-        let mut delete_prompt = false;
-
-        'pages: loop {
-            execute!(stdout(), terminal::Clear(terminal::ClearType::All))?;
-            execute!(stdout(), MoveTo(0,0))?;
-
-            execute!(stdout(), Print(format!("Use arrow keys to navigate\n")))?;
-            execute!(stdout(), Print(format!("Enter to Select | Esc to Quit | Del to Delete | S to Show Hidden Items: {}\n", show_empty_folders)))?;
-            execute!(stdout(), Print(format!("If you have mpv, hit Q to play all media in current folder\n")))?;
-            execute!(stdout(), Print(format!("\\{}\n", sub_dir.strip_prefix(&prefix).unwrap().to_str().unwrap())))?;
-            execute!(stdout(), Print(format!("List of animes | Page {} of {}: \n", current_page, max_pages)))?;
-
-            let current_position = (current_page - 1) * LISTINGS_PER_PAGE;
-            let clp = |cp| {
-                if cp != max_pages {
-                    LISTINGS_PER_PAGE
-                } else {
-                    listings_on_last_page
-                }
-            };
-            if listings.len() > 0 {
-                for i in current_position..(current_position + clp(current_page)) {
-                    if listings[i].is_dir {
-                        execute!(stdout(), Print(format!("[ ] {} | {} episode(s)\n", listings[i].name, listings[i].episode_count)))?;
-                    } else {
-                        execute!(stdout(), Print(format!("[ ] {}\n", listings[i].name)))?;
-                    }
-                }
-            } else {
-                execute!(stdout(), Print(format!("< > Nothing here")))?;
-                execute!(stdout(), MoveTo(1, tip_cursor_offset), SavePosition)?;
-                current_selected_row = 0;
-            }
-            execute!(stdout(), RestorePosition)?;
-
-            if clear_input_buffer { // Clear any inputs made during frozen terminal while movie is playing
-                'clear: loop {
-                    if poll(Duration::from_millis(50))? {
-                        match read()? {
-                            _ => {}
-                        }
-                    } else {
-                        break 'clear
-                    }
-                }
-
-                clear_input_buffer = false;
-            }
-
-            'input: loop {
-                let last_page = current_page;
-                let index = current_position + current_selected_row;
-
-                match read()? {
-                    Event::Key(event) => {
-                        if listings.len() == 0 { // Really make sure that a user can't mess around in empty dir
-                            match event.code {
-                                KeyCode::Esc => {
-                                    if sub_dir == anime_dir {
-                                        break 'main; // Bye
-                                    } else {
-                                        sub_dir.pop();
-                                        current_page = 1;
-                                        current_selected_row = 0;
-                                        break 'pages;
-                                    }
-                                },
-                                _ => { break 'input; }
-                            }
-                        }
-                        if delete_prompt {
-                            match event.code {
-                                KeyCode::Delete => {
-                                    match delete_item(listings[index].clone()) {
-                                        Ok(_) => execute!(stdout(), Print(format!("{} successfully deleted\n", listings[index].name)))?,
-                                        Err(e) => execute!(stdout(), Print(format!("{}\n", e)))?,
-                                    }
-                                    match read()? { _ => {} } // wait for acknowledgement
-
-                                    current_page = 1;
-                                    current_selected_row = 0;
-                                    break 'pages;
-                                },
-                                _ => {
-                                    delete_prompt = false;
-                                    break 'input;
-                                }
-                            }
-                        }
-                        match event.code {
-                            KeyCode::Up => {
-                                if current_selected_row != 0 { current_selected_row -= 1; }
-                            },
-                            KeyCode::Down => {
-                                if current_selected_row != clp(current_page) - 1 { current_selected_row += 1; }
-                            },
-                            KeyCode::Left => {
-                                if current_page != 1 { current_page -= 1; }
-                            },
-                            KeyCode::Right => {
-                                if current_page != max_pages { current_page += 1; }
-                            },
-                            KeyCode::Char('q') | KeyCode::Char('Q') => {
-                                if let Some(_) = listings.iter().find(|x| x.is_media) {
-                                    let handle = call_play_videos(listings.clone());
-                                    execute!(stdout(), terminal::Clear(terminal::ClearType::All))?;
-                                    execute!(stdout(), SavePosition, MoveTo(0,0))?;
-
-                                    execute!(stdout(), Print(format!("Now playing all episodes in {}\n", sub_dir.file_name().and_then(OsStr::to_str).unwrap().to_owned())))?;
-                                    clear_input_buffer = true;
-                                    handle.join().unwrap();
-                                }
-                                break 'input;
-                            },
-                            KeyCode::Enter | KeyCode::Char(' ') => {
-                                if listings[index].is_dir {
-                                    sub_dir = sub_dir.join(&listings[index].name);
-                                    current_page = 1;
-                                    current_selected_row = 0;
-                                    break 'pages;
-                                } else {
-                                    if listings[index].is_media {
-                                        let handle = call_play_video(listings[index].clone());
-
-                                        execute!(stdout(), terminal::Clear(terminal::ClearType::All))?;
-                                        execute!(stdout(), SavePosition, MoveTo(0,0))?;
-                                        execute!(stdout(), Print(format!("Now playing {}\n", listings[index].name)))?;
-
-                                        clear_input_buffer = true;
-                                        handle.join().unwrap();
-                                        break 'input;
-                                    }
-                                }
-                            },
-                            KeyCode::Esc => {
-                                if sub_dir == anime_dir {
-                                    break 'main; // Bye
-                                } else {
-                                    sub_dir.pop();
-                                    current_page = 1;
-                                    current_selected_row = 0;
-                                    break 'pages;
-                                }
-                            },
-                            KeyCode::Delete => {
-                                if !delete_prompt {
-                                    execute!(stdout(), terminal::Clear(terminal::ClearType::All))?;
-                                    execute!(stdout(), MoveTo(0,0))?;
-                                    execute!(stdout(), Print(format!("Are you sure you want to delete {}?\n", listings[index].name)))?;
-                                    execute!(stdout(), Print(format!("Press delete to confirm, press any key to cancel\n")))?;
-                                    execute!(stdout(), MoveTo(0,2))?;
-                                    delete_prompt = true;
-                                }
-                            },
-                            KeyCode::Char('s') | KeyCode::Char('S') => {
-                                show_empty_folders = !show_empty_folders;
-                                current_page = 1;
-                                current_selected_row = 0;
-                                break 'pages;
-                            },
-                            _ => {},
-                        }
-                    },
-                    _ => {}
-                }
-
-                if last_page != current_page {
-                    if current_selected_row >= clp(current_page) { // If current cursor location is bigger than next page's max location
-                        current_selected_row = clp(current_page) - 1;
-                        execute!(stdout(), MoveTo(1, tip_cursor_offset + current_selected_row as u16))?;
-                    }
-                    execute!(stdout(), SavePosition)?;
-                    break 'input; // Refresh pages
-                }
-                if !delete_prompt {
-                    execute!(stdout(), MoveTo(1, tip_cursor_offset + current_selected_row as u16))?;
-                }
-            }
-        }
-    }
-    execute!(stdout(), LeaveAlternateScreen)?;
-    Ok(())
-}
-
-fn get_anime_listings(anime_dir: PathBuf, show_empty_folders: bool) -> Vec<AnimeListing> {
+/// Folders holding at least one episode and media files directly in `anime_dir`.
+/// With `show_empty_folders`, everything else in there is listed too
+pub fn get_anime_listings(anime_dir: PathBuf, show_empty_folders: bool) -> Vec<AnimeListing> {
     let mut anime_list = vec![];
-    for entry in anime_dir.read_dir().expect("Reading anime dir failed") {
+    let watch_states = anime_history::watch_states();
+    let entries = match anime_dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return anime_list,
+    };
+    for entry in entries {
         if let Ok(entry) = entry {
             if entry.path().is_dir() {
-                let listing_episode_count = get_episode_count(entry.path());
-                if listing_episode_count > 0 || show_empty_folders {
+                let contents = folder_contents(&entry.path(), &watch_states);
+                if contents.episodes > 0 || show_empty_folders {
                     let listing = AnimeListing {
-                        name: entry.path().file_name().and_then(OsStr::to_str).unwrap().to_owned(),
+                        name: entry.file_name().to_string_lossy().to_string(),
                         path: entry.path(),
                         is_dir: entry.path().is_dir(),
-                        episode_count: listing_episode_count,
+                        episode_count: contents.episodes,
+                        season_count: contents.seasons,
+                        watched_count: contents.watched,
                         is_media: false,
+                        watch_state: None,
+                        size: contents.size,
+                        modified: entry.metadata().and_then(|metadata| metadata.modified()).ok(),
+                        last_watched: contents.last_watched,
                     };
                     anime_list.push(listing);
                 }
@@ -263,13 +82,21 @@ fn get_anime_listings(anime_dir: PathBuf, show_empty_folders: bool) -> Vec<Anime
                 match entry.path().extension().and_then(OsStr::to_str) {
                     Some(ext) => {
                         let is_media_file = is_valid_media_file(ext);
-                        if is_media_file || show_empty_folders {
+                        if is_media_file || anime_playlist::is_playlist(&entry.path()) || show_empty_folders {
+                            let watch_state = watch_states.get(&anime_history::absolute_path(&entry.path())).cloned();
+                            let metadata = entry.metadata().ok();
                             let episode = AnimeListing {
-                                name: entry.path().file_name().and_then(OsStr::to_str).unwrap().to_owned(),
+                                name: entry.file_name().to_string_lossy().to_string(),
                                 path: entry.path(),
                                 is_dir: false,
                                 episode_count: 0,
+                                season_count: 0,
+                                watched_count: 0,
                                 is_media: is_media_file,
+                                size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+                                modified: metadata.and_then(|metadata| metadata.modified().ok()),
+                                last_watched: watch_state.as_ref().map(|state| state.time),
+                                watch_state,
                             };
                             anime_list.push(episode);
                         }
@@ -279,13 +106,63 @@ fn get_anime_listings(anime_dir: PathBuf, show_empty_folders: bool) -> Vec<Anime
             }
         }
     }
+    // Season 2 before Season 10, episode 2 before episode 10
+    anime_sort::sort_listings(&mut anime_list, SortOrder::Natural);
 
     anime_list
 }
 
-fn get_episode_count(folder_path: PathBuf) -> u32 {
+/// What the listing of a folder says about everything under it
+#[derive(Default)]
+struct FolderContents {
+    episodes: u32,
+    watched: u32,
+    /// `Season NN` folders with episodes directly in the folder
+    seasons: u32,
+    size: u64,
+    /// When an episode in it was last played
+    last_watched: Option<i64>,
+}
+
+/// Everything a listing needs to know about `folder_path`, in a single walk through it
+fn folder_contents(folder_path: &Path, watch_states: &HashMap<PathBuf, WatchState>) -> FolderContents {
+    let mut contents = FolderContents::default();
+    let entries = match folder_path.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return contents,
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.is_dir() {
+            let inner = folder_contents(&path, watch_states);
+            if inner.episodes > 0 && anime_organize::folder_season(&path).is_some() {
+                contents.seasons += 1;
+            }
+            contents.episodes += inner.episodes;
+            contents.watched += inner.watched;
+            contents.size += inner.size;
+            contents.last_watched = contents.last_watched.max(inner.last_watched);
+            continue;
+        }
+        contents.size += path.metadata().map_or(0, |metadata| metadata.len());
+        if path.extension().and_then(OsStr::to_str).map_or(false, is_valid_media_file) {
+            contents.episodes += 1;
+            if let Some(state) = watch_states.get(&anime_history::absolute_path(&path)) {
+                contents.watched += state.completed as u32;
+                contents.last_watched = contents.last_watched.max(Some(state.time));
+            }
+        }
+    }
+    contents
+}
+
+/// Number of media files anywhere under `folder_path`
+pub fn get_episode_count(folder_path: PathBuf) -> u32 {
     let mut num_episodes = 0;
-    for entry in folder_path.read_dir().expect("Reading anime sub dir failed") {
+    let entries = match folder_path.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return num_episodes,
+    };
+    for entry in entries {
         if let Ok(entry) = entry {
             if entry.path().is_file() {
                 match entry.path().extension().and_then(OsStr::to_str) {
@@ -303,130 +180,202 @@ fn get_episode_count(folder_path: PathBuf) -> u32 {
     num_episodes
 }
 
-fn delete_item(listing: AnimeListing) -> std::io::Result<()> {
-    //execute!(stdout(), Print(format!("Deleting {}\n", path.file_name().and_then(OsStr::to_str).unwrap())))?;
-    if listing.is_dir {
-        fs::remove_dir_all(listing.path)?;
-    } else {
-        fs::remove_file(listing.path)?;
+/// Episodes under `series_path` in watching order: by season, then episode. Files without an episode number come last
+fn local_episodes(series_path: &Path) -> Vec<LocalEpisode> {
+    let mut episodes = vec![];
+    let mut folders = vec![series_path.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let entries = match folder.read_dir() {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.is_dir() {
+                folders.push(path);
+            } else if path.extension().and_then(OsStr::to_str).map_or(false, is_valid_media_file) {
+                let release = anime_release::parse(path.file_name().and_then(OsStr::to_str).unwrap_or(""));
+                let season = release.season.or(anime_organize::folder_season(&folder)).unwrap_or(1);
+                episodes.push(LocalEpisode { path, season, release });
+            }
+        }
     }
-    Ok(())
+    episodes.sort_by(|a, b| (a.release.episode.is_none(), a.season, &a.release.episode, &a.path)
+        .cmp(&(b.release.episode.is_none(), b.season, &b.release.episode, &b.path)));
+    episodes
 }
 
-fn call_play_video(episode: AnimeListing) -> thread::JoinHandle<()> {
-    let mut filename = vec![];
-    filename.push(episode.name);
-    let dir_path = episode.path.parent().unwrap().to_path_buf();
-
-    play_video(filename, dir_path)
+/// Episodes under `series_path` in watching order. With `unwatched`, only the ones that weren't watched to the end,
+/// what's left to watch
+pub fn episode_paths(series_path: &Path, unwatched: bool) -> Vec<PathBuf> {
+    let watch_states = anime_history::watch_states();
+    local_episodes(series_path).into_iter()
+        .map(|episode| episode.path)
+        .filter(|path| !unwatched || !watch_states.get(&anime_history::absolute_path(path)).map_or(false, |state| state.completed))
+        .collect()
 }
 
-fn call_play_videos(listings: Vec<AnimeListing>) -> thread::JoinHandle<()> {
-    let mut episodes = vec![];
-    for listing in listings {
-        if !listing.is_dir && is_valid_media_file(listing.path.extension().and_then(OsStr::to_str).unwrap()) {
-            episodes.push(listing);
+/// The first episode under `series_path` that wasn't watched to the end. When the episode after the
+/// last watched one is missing, e.g. 3 after watching 1 and 2 with 4 on disk, that one is next
+pub fn next_episode(series_path: &Path) -> NextEpisode {
+    let episodes = local_episodes(series_path);
+    let watch_states = anime_history::watch_states();
+    let watched = |episode: &LocalEpisode| watch_states.get(&anime_history::absolute_path(&episode.path)).map_or(false, |state| state.completed);
+
+    let first_unwatched = episodes.iter().position(|episode| !watched(episode));
+    let last_watched = match first_unwatched {
+        Some(0) => None,
+        Some(i) => episodes.get(i - 1),
+        None => episodes.last(),
+    };
+    // A later season already on disk means the last one was finished
+    let moved_on = |last: &LocalEpisode| first_unwatched.map_or(false, |i| episodes[i].season > last.season);
+    if let Some(last) = last_watched.filter(|last| !moved_on(last)) {
+        let following = match last.release.episode {
+            Some(Episode::Regular(n)) | Some(Episode::Half(n)) => Some(n + 1),
+            _ => None,
+        };
+        if let Some(following) = following {
+            let on_disk = episodes.iter().any(|e| e.season == last.season && e.release.episode == Some(Episode::Regular(following)));
+            if !on_disk {
+                let title = match last.release.title.as_str() {
+                    "" => series_path.file_name().and_then(OsStr::to_str).unwrap_or("").to_string(),
+                    title => title.to_string(),
+                };
+                return NextEpisode::Download {
+                    title,
+                    season: last.season,
+                    episode: following,
+                    resolution: last.release.resolution,
+                    dir: last.path.parent().unwrap_or(series_path).to_path_buf(),
+                };
+            }
         }
     }
-    let filenames: Vec<String> = episodes.clone().into_iter().map(|episode| episode.name).collect();
-    let dir_path = episodes[0].path.parent().unwrap().to_path_buf();
 
-    play_video(filenames, dir_path)
+    match first_unwatched {
+        Some(i) => NextEpisode::Play(episodes[i].path.clone()),
+        None => NextEpisode::Nothing,
+    }
+}
+
+/// Number of `Season NN` folders with episodes directly under `series_path`
+pub fn get_season_count(series_path: PathBuf) -> u32 {
+    match series_path.read_dir() {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| anime_organize::folder_season(&entry.path()).is_some())
+            .filter(|entry| entry.path().is_dir() && get_episode_count(entry.path()) > 0)
+            .count() as u32,
+        Err(_) => 0,
+    }
+}
+
+/// `<anime_dir>/<Series>/Season NN`, or just the series folder for something without seasons like a movie.
+/// A series folder that's already there under a different capitalisation is reused
+pub fn series_dir(anime_dir: &Path, series: &str, season: Option<u32>) -> PathBuf {
+    let series = anime_organize::clean_name(series);
+    let existing = anime_dir.read_dir().ok().and_then(|entries| entries
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.path().is_dir() && entry.file_name().to_str().map_or(false, |name| name.eq_ignore_ascii_case(&series))));
+    let series_path = match existing {
+        Some(entry) => entry.path(),
+        None => anime_dir.join(&series),
+    };
+
+    match season {
+        Some(season) => series_path.join(format!("{}{:02}", SEASON_PREFIX, season)),
+        None => series_path,
+    }
+}
+
+/// The finished file if there is one, otherwise the .part still being downloaded so the player can stream it
+fn playable_path(video_path: &Path) -> PathBuf {
+    let part_path = anime_part::part_path(video_path);
+    if !video_path.is_file() && part_path.is_file() {
+        part_path
+    } else {
+        video_path.to_path_buf()
+    }
 }
 
-#[cfg(feature = "mpv")]
+/// Plays `filenames` in `dir_path` in order with the player from the config, remembering where playback got to.
+/// Files still being downloaded are waited for, or streamed when the player can follow a growing .part file
 pub fn play_video(filenames: Vec<String>, dir_path: PathBuf) -> thread::JoinHandle<()> {
     thread::spawn(move || {
         thread::sleep(std::time::Duration::from_secs(1));
-        let mut i = 0;
-        let mut timeout = 0;
-        let mut filename = &filenames[i];
-        let video_path = dir_path.join(filename);
-        while timeout < 6 { //Initial connection waiting
-            if !video_path.is_file() {
-                timeout += 1;
+        let mut player = match anime_player::from_config(&anime_config::Config::load()) {
+            Ok(player) => player,
+            Err(e) => { eprintln!("{}", e); return },
+        };
+        let streams = player.streams();
+        let available = |path: &Path| if streams { playable_path(path).is_file() } else { path.is_file() };
+        let paths: Vec<PathBuf> = filenames.iter().map(|filename| anime_history::absolute_path(&dir_path.join(filename))).collect();
+
+        let mut next = 0;
+        while next < paths.len() {
+            let mut timeout = 0;
+            let (mut part_size, mut grown) = (None, Instant::now());
+            while timeout < 6 && !available(&paths[next]) { //Initial connection waiting
+                // Players that can't follow a growing .part file wait for the download to finish,
+                // as long as it keeps growing: a transfer that failed leaves its .part behind
+                match std::fs::metadata(anime_part::part_path(&paths[next])).map(|metadata| metadata.len()) {
+                    Err(_) => timeout += 1,
+                    Ok(size) if part_size != Some(size) => {
+                        part_size = Some(size);
+                        grown = Instant::now();
+                    },
+                    Ok(_) if grown.elapsed() >= PART_STALL_LIMIT => break,
+                    Ok(_) => {},
+                }
                 thread::sleep(std::time::Duration::from_secs(5));
-            } else {
-                break;
             }
-        }
-        let mut mpv_builder = mpv::MpvHandlerBuilder::new().expect("Failed to init MPV builder");
-        if video_path.is_file() {
-            let video_path = video_path
-                .to_str()
-                .expect("Expected a string for Path, got None");
-            mpv_builder.set_option("osc", true).unwrap();
-            mpv_builder
-                .set_option("input-default-bindings", true)
-                .unwrap();
-            mpv_builder.set_option("input-vo-keyboard", true).unwrap();
-            let mut mpv = mpv_builder.build().expect("Failed to build MPV handler");
-            mpv.command(&["loadfile", video_path as &str])
-                .expect("Error loading file");
-            'main: loop {
-                while let Some(event) = mpv.wait_event(0.0) {
-                    //println!("{:?}", event);
-                    match event {
-                        mpv::Event::Shutdown => {
-                            break 'main;
-                        }
-                        mpv::Event::Idle => {
-                            if i >= filenames.len() {
-                                break 'main;
-                            }
-                        }
-                        mpv::Event::EndFile(Ok(mpv::EndFileReason::MPV_END_FILE_REASON_EOF)) => {
-                            i += 1;
-                            if i >= filenames.len() {
-                                break 'main;
-                            }
-                            filename = &filenames[i];
-                            let next_video_path = dir_path.join(filename);
-                            if next_video_path.is_file() {
-                                let next_video_path = next_video_path
-                                    .to_str()
-                                    .expect("Expected a string for Path, got None");
-                                mpv.command(&["loadfile", next_video_path as &str])
-                                    .expect("Error loading file");
-                            } else {
-                                eprintln!(
-                                    "A file is required; {} is not a valid file",
-                                    next_video_path.to_str().unwrap()
-                                );
-                            }
-                        }
-                        _ => {}
-                    };
+            if !available(&paths[next]) {
+                match part_size {
+                    Some(_) => eprintln!("{} stopped downloading, play it once the download is done", paths[next].display()),
+                    None => eprintln!("A file is required; {} is not a valid file", paths[next].display()),
                 }
+                return;
             }
-        } else {
-            eprintln!(
-                "A file is required; {} is not a valid file",
-                video_path.to_str().unwrap()
-            );
-        }
-    })
-}
 
-#[cfg(not(feature = "mpv"))]
-pub fn play_video(filenames: Vec<String>, dir_path: PathBuf) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        thread::sleep(std::time::Duration::from_secs(1));
-        let filename = &filenames[0];
-        let video_path = dir_path.join(filename);
-
-        let mut timeout = 0;
-        while timeout < 6 { //Initial connection waiting
-            if !video_path.is_file() {
-                timeout += 1;
-                thread::sleep(std::time::Duration::from_secs(5));
-            } else {
-                break;
+            // Everything that's there already goes in one go, the player comes back for the rest
+            let watch_states = anime_history::watch_states();
+            let items: Vec<anime_player::Item> = paths[next..].iter()
+                .take_while(|path| available(path))
+                .map(|path| anime_player::Item {
+                    path: if streams { playable_path(path) } else { path.clone() },
+                    start: watch_states.get(path).and_then(WatchState::resume_position),
+                })
+                .collect();
+            let batch = &paths[next..next + items.len()];
+            let mut current: Option<(usize, f64, f64)> = None; // file, position, duration
+            let mut finished = None;
+            let mut last_save = Instant::now();
+            let result = player.play(&items, &mut |event| match event {
+                anime_player::PlayerEvent::Position { index, position, duration } => {
+                    current = Some((index, position, duration));
+                    if position > 0.0 && last_save.elapsed() >= SAVE_INTERVAL { // so a crash doesn't lose more than a few seconds
+                        let _ = anime_history::save_watch_state(&batch[index], position, duration, false);
+                        last_save = Instant::now();
+                    }
+                },
+                anime_player::PlayerEvent::Finished(index) => {
+                    let duration = current.filter(|&(file, _, _)| file == index).map_or(0.0, |(_, _, duration)| duration);
+                    let _ = anime_history::save_watch_state(&batch[index], duration, duration, true);
+                    current = None;
+                    finished = Some(index);
+                },
+            });
+            if let Some((index, position, duration)) = current.filter(|&(_, position, _)| position > 0.0) {
+                let _ = anime_history::save_watch_state(&batch[index], position, duration, false);
+            }
+            if let Err(e) = result {
+                eprintln!("Could not play {}: {}", batch[0].display(), e);
+                return;
+            }
+            match finished {
+                Some(index) if index + 1 == items.len() => next += items.len(),
+                _ => return, // closed before the end
             }
         }
-        match opener::open(video_path) {
-            Ok(_) => {},
-            Err(e) => { eprintln!("{:?}", e)},
-        };
     })
-}
\ No newline at end of file
+}
@@ -0,0 +1,58 @@
+//! Find anime on XDCC bots, download it and keep track of what's on disk.
+//!
+//! - [`anime_find::search`] and [`anime_find::find_package`] query the nibl search API
+//! - [`anime_dl::download`] fetches packs over IRC/DCC and reports [`anime_dl::DownloadEvent`]s
+//! - [`anime_watch::get_anime_listings`] scans a local anime folder
+//! - [`anime_episode::EpisodeSpec`] parses episode selections like `1-5,8,latest` and
+//!   [`anime_release::parse`] reads episode, version and resolution out of release names
+//! - [`anime_probe::probe`] reads length, tracks and chapters from Matroska and MP4 headers
+//! - [`anime_player::Player`] plays files in libmpv, mpv, VLC, MPlayer or a custom command and reports where playback got to
+//!
+//! Networking is async on tokio, [`anime_dl::download`] runs its own runtime for synchronous callers.
+//! [`anime_dl::cancel`] winds every running download down, [`anime_dl::reset_cancel`] lets new ones start again.
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//! use anime_cli::{anime_dl, anime_find};
+//!
+//! let runtime = tokio::runtime::Runtime::new().unwrap();
+//! let package = runtime.block_on(anime_find::find_package(&"sakamoto 720".to_string(), &Some(1), &None)).unwrap();
+//! let request = anime_dl::IRCRequest::for_packages(&[package], None);
+//! anime_dl::download(request, PathBuf::from("sakamoto"), |event| println!("{:?}", event)).unwrap();
+//! ```
+
+#[cfg(feature = "mpv")]
+extern crate mpv;
+
+pub mod anime_config;
+pub mod anime_disk;
+pub mod anime_dl;
+pub mod anime_episode;
+pub mod anime_find;
+pub mod anime_format;
+pub mod anime_history;
+pub mod anime_irc;
+pub mod anime_organize;
+pub mod anime_part;
+pub mod anime_player;
+pub mod anime_playlist;
+pub mod anime_probe;
+pub mod anime_proxy;
+pub mod anime_queue;
+pub mod anime_release;
+pub mod anime_sort;
+pub mod anime_trash;
+pub mod anime_watch;
+
+const AUDIO_EXTENSIONS: &'static [&'static str] = &["aif", "cda", "mid", "midi", "mp3",
+                                                    "mpa", "ogg", "wav", "wma", "wpl"];
+
+const VIDEO_EXTENSIONS: &'static [&'static str] = &["3g2", "3gp", "avi", "flv", "h264",
+                                                    "m4v", "mkv", "mov", "mp4", "mpg",
+                                                    "mpeg", "rm", "swf", "vob", "wmv"];
+
+/// Whether a file with extension `ext` (without the dot, in any case) is something a player can open
+pub fn is_valid_media_file(ext: &str) -> bool {
+    let ext = ext.to_ascii_lowercase();
+    AUDIO_EXTENSIONS.contains(&ext.as_str()) || VIDEO_EXTENSIONS.contains(&ext.as_str())
+}